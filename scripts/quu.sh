@@ -14,20 +14,20 @@ fi
 
 mkdir -p /tmp/tuun
 
-# Gather selected songs
+# Gather songs from the generated all playlist, which respects tuun's library
+# settings, falling back to searching the song directory
 #
-# Then, write them to the queue, and apply some fixes:
-# 1. Prepend the song directory
-# 2. Change \ to \\ to appease mpv
-# 3. Change " to \" to appease mpv
-find "$SONG_DIR" -maxdepth 1 -mindepth 1 -type f \
-    \( -iname '*.mp3' -o -iname '*.opus' -o -iname '*.wav' -o -iname '*.m4a' -o -iname '*.ogg' -o -iname '*.flac' \) |
-    sed 's,.*/,,'       | # strip full path
-    shuf                | # shuffle
-    fzm                 | # write queue
-    sed -e "s,^,$SONG_DIR/,"    \
-        -e 's,\\,\\\\,g'        \
-        -e 's,",\\",g'          \
+# Then, prepend the song directory and write the selected ones to the queue
+if [ -s /tmp/tuun/all.tpl ]; then
+    cat /tmp/tuun/all.tpl
+else
+    find "$SONG_DIR" -mindepth 1 -type f \
+        \( -iname '*.mp3' -o -iname '*.opus' -o -iname '*.wav' -o -iname '*.m4a' -o -iname '*.ogg' -o -iname '*.flac' \)
+fi |
+    sed "s,^$SONG_DIR/,,"   | # strip the song directory
+    shuf                    | # shuffle
+    fzm                     | # write queue
+    sed -e "s,^\([^/]\),$SONG_DIR/\1," \
         > /tmp/tuun/_quu.tpl
 
 if [ -s /tmp/tuun/_quu.tpl ]; then
@@ -1,5 +1,12 @@
 #!/usr/bin/env sh
 
+# Subcommands talk to an already running instance, so skip the lock dance
+case "$1" in
+    ctl|lastfm|love|queue|report|stats|status|unlove|-h|--help|-V|--version)
+        exec %LIBEXECDIR%/tuun "$@"
+        ;;
+esac
+
 # https://github.com/tox-wtf/tuun/issues/2
 if [ -e "/tmp/tuun/tuun.lock" ]; then
     if pidof %LIBEXECDIR%/tuun; then
@@ -14,6 +21,7 @@ fi
 cleanup() {
     rm -f "/tmp/tuun/quu.tpl"
     rm -f "/tmp/tuun/tuun.lock"
+    rm -f "/tmp/tuun/tuunsocket"
     pkill -f %LIBEXECDIR%/tuun >/dev/null 2>&1
     [ -r "/tmp/tuun/tuun-mpv.pid" ] && kill "$(cat /tmp/tuun/tuun-mpv.pid)" >/dev/null 2>&1
     rm -f "/tmp/tuun/tuun-mpv.pid"
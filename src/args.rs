@@ -1,4 +1,21 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{
+    Parser,
+    Subcommand,
+};
+
+use crate::{
+    queue::Mode,
+    report::{
+        Format,
+        Period,
+    },
+    stats::{
+        Bucket,
+        Range,
+    },
+};
 
 /// Tuun: A simple music player using MPV as a backend
 #[derive(Parser, Debug)]
@@ -13,6 +30,166 @@ pub struct Args {
     /// Example: ~/Music/playlist.tpl
     #[arg(short, long)]
     pub playlist: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Control a running instance of tuun
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+
+    /// Manage queued tracks
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+
+    /// Print the current track and player state
+    ///
+    /// Prints JSON unless a format string is given.
+    Status {
+        /// Format string with placeholders like {artist} and {progress}
+        ///
+        /// Example: '{artist} - {title} [{progress}/{duration}]'
+        #[arg(short, long)]
+        format: Option<String>,
+    },
+
+    /// Print listening stats from the play history
+    ///
+    /// Works whether or not tuun is running.
+    Stats {
+        /// How far back to look
+        #[arg(short, long, value_enum, default_value_t)]
+        range: Range,
+
+        /// Whether to count plays per day or per hour of the day
+        #[arg(short, long, value_enum, default_value_t)]
+        by: Bucket,
+
+        /// How many artists, albums, and tracks to list
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+
+        /// Print the stats as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Generate a "wrapped"-style report of a year or month of listening
+    ///
+    /// Example: tuun report --period 2026 --output wrapped.html
+    Report {
+        /// The year (2026) or month (2026-03) to cover
+        #[arg(short, long)]
+        period: Period,
+
+        /// What to render the report as
+        #[arg(short, long, value_enum, default_value_t)]
+        format: Format,
+
+        /// Where to write the report (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// How many tracks, artists, and albums to list
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Love the current track
+    ///
+    /// Loved tracks are listed in loved.tpl and synced to Last.fm and Libre.fm.
+    Love,
+
+    /// Unlove the current track
+    Unlove,
+
+    /// Manage tuun's Last.fm session
+    Lastfm {
+        #[command(subcommand)]
+        action: LastfmAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CtlAction {
+    /// Toggle pause
+    Pause,
+
+    /// Skip to the next track
+    Next,
+
+    /// Go back to the previous track
+    Prev,
+
+    /// Seek relative to the current position
+    ///
+    /// Example: tuun ctl seek -5
+    Seek {
+        /// Seconds to seek by (negative seeks backward)
+        #[arg(allow_negative_numbers = true)]
+        seconds: f64,
+    },
+
+    /// Toggle looping the current track
+    Loop,
+
+    /// Toggle mute
+    Mute,
+
+    /// Set the volume, or adjust it with a leading + or -
+    ///
+    /// Example: tuun ctl volume +5
+    Volume {
+        #[arg(allow_hyphen_values = true)]
+        volume: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QueueAction {
+    /// List upcoming queued tracks
+    List {
+        /// Print the queue as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+
+    /// Queue tracks
+    Add {
+        /// Where to queue the tracks
+        #[arg(short, long, value_enum, default_value_t)]
+        mode: Mode,
+
+        #[arg(required = true)]
+        tracks: Vec<String>,
+    },
+
+    /// Remove the queued track at a position
+    Remove { position: usize },
+
+    /// Move the queued track at one position to another
+    Move { from: usize, to: usize },
+
+    /// Remove every queued track
+    Clear,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LastfmAction {
+    /// Authorize tuun on the Last.fm website and keep the session key
+    ///
+    /// Once logged in, your Last.fm password can be left out of config.toml.
+    Login,
+
+    /// Forget the stored session key
+    Logout,
 }
 
 pub fn parse_args() -> Args { Args::parse() }
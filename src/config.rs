@@ -10,9 +10,29 @@
 use std::{
     env,
     fs,
-    path::Path,
+    io::Read,
+    ops::Deref,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::{
+        Command,
+        Stdio,
+    },
+    sync::OnceLock,
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
 use serde::Deserialize;
 use tracing::{
     debug,
@@ -24,10 +44,16 @@ use tracing::{
 #[derive(Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct Config {
-    pub lastfm:  LastFMConfig,
-    pub discord: DiscordConfig,
-    pub general: GeneralConfig,
-    pub color:   ColorConfig,
+    pub lastfm:       LastFMConfig,
+    pub librefm:      LibreFMConfig,
+    pub listenbrainz: ListenBrainzConfig,
+    pub maloja:       MalojaConfig,
+    pub discord:      DiscordConfig,
+    pub mpris:        MprisConfig,
+    pub history:      HistoryConfig,
+    pub general:      GeneralConfig,
+    pub color:        ColorConfig,
+    pub library:      Vec<LibraryConfig>,
 }
 
 impl Default for LastFMConfig {
@@ -35,10 +61,14 @@ impl Default for LastFMConfig {
         Self {
             used:             false,
             apikey:           String::new(),
+            apikey_cmd:       None,
             secret:           String::new(),
+            secret_cmd:       None,
             user:             String::new(),
             password:         String::new(),
+            password_cmd:     None,
             scrobble_percent: 44,
+            scrobble_rule:    ScrobbleRule::default(),
         }
     }
 }
@@ -48,10 +78,103 @@ impl Default for LastFMConfig {
 pub struct LastFMConfig {
     pub used:             bool,
     pub apikey:           String,
+    /// A command that prints the API key, used instead of `apikey`
+    pub apikey_cmd:       Option<String>,
     pub secret:           String,
+    /// A command that prints the secret, used instead of `secret`
+    pub secret_cmd:       Option<String>,
     pub user:             String,
     pub password:         String,
+    /// A command that prints the password, used instead of `password`
+    pub password_cmd:     Option<String>,
     pub scrobble_percent: u8,
+    pub scrobble_rule:    ScrobbleRule,
+}
+
+/// When a track has been listened to long enough to scrobble
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrobbleRule {
+    /// After `scrobble_percent` of the track
+    #[default]
+    Percent,
+    /// Last.fm's official rules
+    LastFM,
+}
+
+impl Default for LibreFMConfig {
+    fn default() -> Self {
+        // Libre.fm takes any 32-character key and secret
+        let key = "tuun".repeat(8);
+        Self {
+            used:         false,
+            api_url:      "https://libre.fm/2.0/".to_owned(),
+            apikey:       key.clone(),
+            secret:       key,
+            user:         String::new(),
+            password:     String::new(),
+            password_cmd: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LibreFMConfig {
+    pub used:         bool,
+    /// The API root of Libre.fm or another GNU FM instance
+    pub api_url:      String,
+    pub apikey:       String,
+    pub secret:       String,
+    pub user:         String,
+    pub password:     String,
+    /// A command that prints the password, used instead of `password`
+    pub password_cmd: Option<String>,
+}
+
+impl Default for ListenBrainzConfig {
+    fn default() -> Self {
+        Self {
+            used:      false,
+            token:     String::new(),
+            token_cmd: None,
+            api_url:   "https://api.listenbrainz.org".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ListenBrainzConfig {
+    pub used:      bool,
+    pub token:     String,
+    /// A command that prints the token, used instead of `token`
+    pub token_cmd: Option<String>,
+    /// The API root, without `/1/`
+    pub api_url:   String,
+}
+
+impl Default for MalojaConfig {
+    fn default() -> Self {
+        Self {
+            used:      false,
+            api_url:   "http://localhost:42010/apis/listenbrainz".to_owned(),
+            token:     String::new(),
+            token_cmd: None,
+        }
+    }
+}
+
+/// Maloja is reached through its `ListenBrainz`-compatible API
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MalojaConfig {
+    pub used:      bool,
+    pub api_url:   String,
+    /// A Maloja API key
+    pub token:     String,
+    /// A command that prints the API key, used instead of `token`
+    pub token_cmd: Option<String>,
 }
 
 impl Default for DiscordConfig {
@@ -81,6 +204,26 @@ pub struct DiscordConfig {
     pub timeout:      u64,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MprisConfig {
+    pub used: bool,
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self { Self { used: true } }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub used: bool,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self { Self { used: true } }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct ColorConfig {
@@ -99,6 +242,7 @@ impl Default for ColorConfig {
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Deserialize, Debug)]
 #[serde(default)]
 pub struct GeneralConfig {
@@ -106,6 +250,13 @@ pub struct GeneralConfig {
     pub shuffle:                 bool,
     pub playlist:                String,
     pub music_dir:               String,
+    pub recursive:               bool,
+    pub follow_symlinks:         bool,
+    pub audio_extensions:        Vec<String>,
+    pub watch_library:           bool,
+    pub auto_queue:              bool,
+    /// Seconds since a new track was last modified before it's auto-queued
+    pub auto_queue_min_age:      u64,
     pub recent_length:           usize,
     pub mpv_socket_poll_timeout: usize,
     pub now_playing_delay:       usize,
@@ -126,6 +277,17 @@ impl Default for GeneralConfig {
             playlist:                "/tmp/tuun/all.tpl".to_owned(),
             music_dir:               get_fallback_music_dir()
                 .expect("Couldn't retrieve fallback music directory"),
+            recursive:               true,
+            follow_symlinks:         true,
+            audio_extensions:        [
+                "mp3", "flac", "opus", "ogg", "oga", "m4a", "aac", "wav", "wv", "ape", "alac",
+                "aiff", "mka",
+            ]
+            .map(ToOwned::to_owned)
+            .to_vec(),
+            watch_library:           true,
+            auto_queue:              false,
+            auto_queue_min_age:      10,
             recent_length:           350,
             mpv_socket_poll_timeout: 96,
             now_playing_delay:       2345,
@@ -133,6 +295,33 @@ impl Default for GeneralConfig {
     }
 }
 
+/// A library root
+///
+/// Unset options fall back to those in `[general]`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LibraryConfig {
+    pub path:            String,
+    pub recursive:       Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    /// Whether tracks from this root are included in `all.tpl`
+    pub in_all:          bool,
+    /// Whether tuun should refuse to start if this root is missing
+    pub required:        bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            path:            String::new(),
+            recursive:       None,
+            follow_symlinks: None,
+            in_all:          true,
+            required:        true,
+        }
+    }
+}
+
 impl Config {
     pub fn load() -> Self {
         let home_dir = homedir::my_home()
@@ -167,12 +356,73 @@ impl Config {
         config.general.playlist = config.general.playlist.replacen('~', &home_dir_str, 1);
         config.general.music_dir = config.general.music_dir.replacen("$HOME", &home_dir_str, 1);
         config.general.playlist = config.general.playlist.replacen("$HOME", &home_dir_str, 1);
+        for root in &mut config.library {
+            root.path = root.path.replacen('~', &home_dir_str, 1);
+            root.path = root.path.replacen("$HOME", &home_dir_str, 1);
+        }
+
+        if config.general.auto_queue && !config.general.watch_library {
+            warn!("auto_queue does nothing unless watch_library is also enabled");
+        }
 
         info!("Loaded config");
         debug!("Config: {config:#?}");
         config
     }
 
+    /// # Description
+    /// Replaces secrets that have a `*_cmd` with that command's output.
+    ///
+    /// Only the running instance and `tuun lastfm` need secrets, so this isn't done by
+    /// [`Config::load`]. Call it after the config has been logged so secrets stay out of the log.
+    ///
+    /// A service whose secret couldn't be resolved is disabled rather than left to fail later.
+    pub fn resolve_secrets(&mut self) {
+        let lastfm = &mut self.lastfm;
+        let resolved = [
+            resolve_secret(
+                "lastfm.apikey",
+                &mut lastfm.apikey,
+                lastfm.apikey_cmd.as_deref(),
+            ),
+            resolve_secret(
+                "lastfm.secret",
+                &mut lastfm.secret,
+                lastfm.secret_cmd.as_deref(),
+            ),
+            resolve_secret(
+                "lastfm.password",
+                &mut lastfm.password,
+                lastfm.password_cmd.as_deref(),
+            ),
+        ];
+        disable_unless("lastfm", &mut lastfm.used, resolved.into_iter().all(|r| r));
+
+        let librefm = &mut self.librefm;
+        let resolved = resolve_secret(
+            "librefm.password",
+            &mut librefm.password,
+            librefm.password_cmd.as_deref(),
+        );
+        disable_unless("librefm", &mut librefm.used, resolved);
+
+        let listenbrainz = &mut self.listenbrainz;
+        let resolved = resolve_secret(
+            "listenbrainz.token",
+            &mut listenbrainz.token,
+            listenbrainz.token_cmd.as_deref(),
+        );
+        disable_unless("listenbrainz", &mut listenbrainz.used, resolved);
+
+        let maloja = &mut self.maloja;
+        let resolved = resolve_secret(
+            "maloja.token",
+            &mut maloja.token,
+            maloja.token_cmd.as_deref(),
+        );
+        disable_unless("maloja", &mut maloja.used, resolved);
+    }
+
     fn create_default(config_path: &Path) {
         let datadir = option_env!("DATADIR").unwrap_or("/usr/share/data");
         let default_config_path = Path::new(datadir).join("default_config.toml");
@@ -201,6 +451,138 @@ impl Config {
     }
 }
 
+/// # Description
+/// Holds the config once it's loaded.
+///
+/// `main` sets it when it needs secrets resolved. Otherwise, it's loaded without them the first
+/// time it's read.
+pub struct Loaded(OnceLock<Config>);
+
+impl Loaded {
+    pub const fn new() -> Self { Self(OnceLock::new()) }
+
+    /// # Description
+    /// Sets the config.
+    ///
+    /// # Panics
+    /// If the config was already read or set.
+    pub fn set(&self, config: Config) {
+        // Not `expect`, since that would print the config's secrets
+        assert!(
+            self.0.set(config).is_ok(),
+            "The config was read before it was loaded"
+        );
+    }
+}
+
+impl Deref for Loaded {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        // Tests use the defaults rather than the config of whoever runs them
+        if cfg!(test) {
+            self.0.get_or_init(Config::default)
+        } else {
+            self.0.get_or_init(Config::load)
+        }
+    }
+}
+
+/// How long a `*_cmd` gets to print its secret, which should be long enough to type a passphrase
+const SECRET_CMD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// # Description
+/// Sets a secret to the output of its command, if it has one.
+///
+/// Returns whether the secret could be resolved. On failure, the error is printed and the secret is
+/// left empty rather than falling back to the literal value.
+fn resolve_secret(name: &str, secret: &mut String, cmd: Option<&str>) -> bool {
+    let Some(cmd) = cmd else { return true };
+
+    if !secret.is_empty() {
+        warn!("Both {name} and {name}_cmd are set, so {name} is ignored");
+    }
+
+    match run_secret_cmd(cmd) {
+        | Ok(output) => {
+            debug!("Resolved {name} from {name}_cmd");
+            *secret = output;
+            true
+        },
+        | Err(e) => {
+            // Logs go to a file, so make sure this is seen
+            error!("Failed to resolve {name} from {name}_cmd '{cmd}': {e:#}");
+            eprintln!("tuun: Failed to resolve {name} from {name}_cmd '{cmd}': {e:#}");
+            secret.clear();
+            false
+        },
+    }
+}
+
+/// Disables a service if its secrets couldn't be resolved
+fn disable_unless(service: &str, used: &mut bool, resolved: bool) {
+    if *used && !resolved {
+        error!("Disabling {service} since its secrets couldn't be resolved");
+        eprintln!("tuun: Disabling {service} since its secrets couldn't be resolved");
+        *used = false;
+    }
+}
+
+/// # Description
+/// Runs a command with `sh -c` and returns the first line it prints, like `pass show` would.
+///
+/// The command is killed if it takes longer than [`SECRET_CMD_TIMEOUT`].
+fn run_secret_cmd(cmd: &str) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn sh")?;
+
+    // Read the pipes on their own threads so a chatty command can't fill them and block
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut output = String::new();
+            let _ = pipe.read_to_string(&mut output);
+            output
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().context("Missing stdout")?));
+    let stderr = read(Box::new(child.stderr.take().context("Missing stderr")?));
+
+    let deadline = Instant::now() + SECRET_CMD_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Timed out after {}s", SECRET_CMD_TIMEOUT.as_secs());
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = stderr.trim();
+        if stderr.is_empty() {
+            bail!("Exited with {status}");
+        }
+        bail!("Exited with {status}: {stderr}");
+    }
+
+    let secret = stdout.lines().next().unwrap_or_default().trim();
+    if secret.is_empty() {
+        bail!("Printed nothing");
+    }
+    Ok(secret.to_owned())
+}
+
 /// This function retrieves a fallback config directory.
 fn get_fallback_config_dir() -> Option<String> {
     if let Ok(config_dir) = env::var("XDG_CONFIG_HOME") {
@@ -216,6 +598,26 @@ fn get_fallback_config_dir() -> Option<String> {
     None
 }
 
+/// This function retrieves the cache directory.
+pub fn get_cache_dir() -> Option<PathBuf> { xdg_dir("XDG_CACHE_HOME", ".cache") }
+
+/// This function retrieves the state directory.
+pub fn get_state_dir() -> Option<PathBuf> { xdg_dir("XDG_STATE_HOME", ".local/state") }
+
+/// # Description
+/// Retrieves an XDG base directory, falling back to a path under the home directory.
+///
+/// Per the XDG spec, the variable is ignored if it's empty or not an absolute path.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(var).map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return Some(dir)
+    }
+
+    homedir::my_home().ok().flatten().map(|p| p.join(fallback))
+}
+
 /// This function retrieves a fallback music directory.
 ///
 /// Note that this should only be used to find a default fallback music directory if not set in the
@@ -0,0 +1,135 @@
+// src/ctl.rs
+//! Logic for controlling a running instance of tuun from the command line
+
+use std::io::{
+    self,
+    ErrorKind as IOE,
+};
+
+use anyhow::{
+    Result,
+    bail,
+};
+use serde_json::{
+    Value,
+    json,
+};
+
+use crate::{
+    args::CtlAction,
+    mpv::{
+        SOCK_PATH,
+        command,
+    },
+};
+
+/// Exit code used when no running instance of tuun could be reached
+pub const EXIT_NOT_RUNNING: i32 = 2;
+
+/// # Description
+/// Runs a control action against the running instance and returns an exit code.
+///
+/// Errors are reported on stderr since logging isn't initialized for control commands.
+pub async fn run(action: &CtlAction) -> i32 { exit_code(control(action).await, SOCK_PATH) }
+
+/// # Description
+/// Reports the result of a subcommand on stderr and turns it into an exit code.
+///
+/// `sock` is the socket the subcommand tried to reach.
+pub fn exit_code(result: Result<()>, sock: &str) -> i32 {
+    match result {
+        | Ok(()) => 0,
+        | Err(e) if is_not_running(&e) => {
+            eprintln!("tuun doesn't seem to be running (couldn't connect to {sock})");
+            EXIT_NOT_RUNNING
+        },
+        | Err(e) => {
+            eprintln!("tuun: {e:#}");
+            1
+        },
+    }
+}
+
+async fn control(action: &CtlAction) -> Result<()> {
+    let args = match action {
+        | CtlAction::Pause => json!(["cycle", "pause"]),
+        | CtlAction::Next => json!(["playlist-next"]),
+        | CtlAction::Prev => json!(["playlist-prev"]),
+        | CtlAction::Seek { seconds } => json!(["seek", seconds, "relative", "exact"]),
+        | CtlAction::Mute => json!(["cycle", "mute"]),
+        | CtlAction::Loop => {
+            let looped = get_property("loop-file").await?;
+            let looped = match looped {
+                | Value::Bool(b) => b,
+                | Value::String(s) => s == "inf",
+                | _ => false,
+            };
+            json!(["set", "loop-file", if looped { "no" } else { "inf" }])
+        },
+        | CtlAction::Volume { volume } => {
+            if let Some(delta) = volume.strip_prefix(['+', '-']) {
+                let Ok(delta) = delta.parse::<f64>() else {
+                    bail!("Invalid volume adjustment '{volume}'");
+                };
+                let delta = if volume.starts_with('-') { -delta } else { delta };
+                json!(["add", "volume", delta])
+            } else {
+                let Ok(volume) = volume.parse::<f64>() else {
+                    bail!("Invalid volume '{volume}'");
+                };
+                json!(["set_property", "volume", volume])
+            }
+        },
+    };
+
+    command(args).await?;
+    Ok(())
+}
+
+async fn get_property(property: &str) -> Result<Value> {
+    let response = command(json!(["get_property", property])).await?;
+    Ok(response.get("data").cloned().unwrap_or(Value::Null))
+}
+
+/// Whether an error came from being unable to reach a running instance's socket
+fn is_not_running(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| matches!(e.kind(), IOE::NotFound | IOE::ConnectionRefused))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use super::*;
+    use crate::fake_mpv::{
+        self,
+        FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn volume_is_set_and_adjusted() {
+        let fake = Arc::new(Mutex::new(FakeMpv::default()));
+        let _served = fake_mpv::serve(&fake);
+
+        for volume in ["50", "+5", "-2.5"] {
+            control(&CtlAction::Volume { volume: volume.to_owned() })
+                .await
+                .expect("Failed to change the volume");
+        }
+        assert!(
+            control(&CtlAction::Volume { volume: "loud".to_owned() })
+                .await
+                .is_err()
+        );
+
+        assert_eq!(fake.lock().expect("Fake mpv lock poisoned").commands, [
+            json!(["set_property", "volume", 50.0]),
+            json!(["add", "volume", 5.0]),
+            json!(["add", "volume", -2.5]),
+        ]);
+    }
+}
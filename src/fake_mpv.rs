@@ -0,0 +1,175 @@
+// src/fake_mpv.rs
+//! A fake mpv for tests
+//!
+//! It answers commands over a temporary socket the way mpv's JSON IPC does, keeping a playlist so
+//! queueing can be tested, and records every command it's sent. Serving it points the current
+//! thread's mpv commands at it, so tests should use the default current-thread `#[tokio::test]`.
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    env,
+    fs,
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        Mutex,
+        PoisonError,
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+    },
+};
+
+use serde_json::{
+    Value,
+    json,
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::UnixListener,
+};
+
+thread_local! {
+    /// The socket of the fake mpv serving the current thread, if any
+    static SOCK_PATH: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Numbers sockets so tests running at once don't share one
+static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+/// The socket mpv commands from the current thread are sent to, if a fake mpv is serving it
+pub fn sock_path() -> Option<PathBuf> { SOCK_PATH.with_borrow(Clone::clone) }
+
+#[derive(Debug, Default)]
+pub struct FakeMpv {
+    /// The playlist, as `(filename, id)` pairs
+    pub entries:  Vec<(String, u64)>,
+    /// The index of the playing entry
+    pub current:  usize,
+    /// Every command received, in order
+    pub commands: Vec<Value>,
+    /// Commands answered with an error instead of being carried out
+    failing:      HashSet<String>,
+    next_id:      u64,
+}
+
+impl FakeMpv {
+    /// Starts with a playlist, playing its first entry
+    pub fn with_playlist(filenames: &[&str]) -> Self {
+        let mut fake = Self::default();
+        for filename in filenames {
+            fake.push(filename);
+        }
+        fake
+    }
+
+    /// Makes `command` fail, the way mpv answers a command it can't carry out
+    pub fn fail(mut self, command: &str) -> Self {
+        self.failing.insert(command.to_owned());
+        self
+    }
+
+    pub fn filenames(&self) -> Vec<&str> { self.entries.iter().map(|(f, _)| f.as_str()).collect() }
+
+    fn push(&mut self, filename: &str) -> u64 {
+        self.next_id += 1;
+        self.entries.push((filename.to_owned(), self.next_id));
+        self.next_id
+    }
+
+    fn handle(&mut self, command: &[Value]) -> Value {
+        self.commands.push(Value::from(command.to_vec()));
+
+        let arg = |i: usize| command.get(i).cloned().unwrap_or(Value::Null);
+        let index = |i: usize| {
+            usize::try_from(arg(i).as_u64().expect("Index should be a number"))
+                .expect("Index should fit in usize")
+        };
+
+        match arg(0).as_str().unwrap_or_default() {
+            | name if self.failing.contains(name) => json!({ "error": "invalid parameter" }),
+            | "loadfile" => {
+                assert_eq!(arg(2), "append", "Tracks should be appended, then moved");
+                let id = self.push(arg(1).as_str().unwrap_or_default());
+                json!({ "error": "success", "data": { "playlist_entry_id": id } })
+            },
+            | "get_property" if arg(1) == "playlist" => {
+                let playlist = self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (filename, id))| {
+                        json!({ "filename": filename, "id": id, "current": i == self.current })
+                    })
+                    .collect::<Vec<_>>();
+                json!({ "error": "success", "data": playlist })
+            },
+            // Like mpv, `set` only takes its value as a string
+            | "set" if !arg(2).is_string() => json!({ "error": "invalid parameter" }),
+            | "playlist-move" => {
+                // mpv puts the entry where the target entry was
+                let (from, to) = (index(1), index(2));
+                let entry = self.entries.remove(from);
+                self.entries
+                    .insert(if from < to { to - 1 } else { to }, entry);
+                json!({ "error": "success" })
+            },
+            | _ => json!({ "error": "success" }),
+        }
+    }
+}
+
+/// Stops serving a fake mpv when dropped
+pub struct Served {
+    path: PathBuf,
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        SOCK_PATH.set(None);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Serves a fake mpv on a temporary socket, and points the current thread's mpv commands at it
+pub fn serve(fake: &Arc<Mutex<FakeMpv>>) -> Served {
+    let path = env::temp_dir().join(format!(
+        "tuun-test-mpv-{}-{}.sock",
+        process::id(),
+        SOCKETS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).expect("Failed to bind fake mpv socket");
+
+    let fake = Arc::clone(fake);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let fake = Arc::clone(&fake);
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let request: Value = serde_json::from_str(&line).expect("Invalid JSON");
+                    let command = request["command"].as_array().cloned().unwrap_or_default();
+                    let response = fake
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .handle(&command);
+                    let mut bytes = response.to_string().into_bytes();
+                    bytes.push(b'\n');
+                    let _ = writer.write_all(&bytes).await;
+                }
+            });
+        }
+    });
+
+    SOCK_PATH.set(Some(path.clone()));
+    Served { path }
+}
@@ -0,0 +1,188 @@
+// src/history.rs
+//! Logic for recording play history
+//!
+//! Every play is appended as a line of JSON to `$XDG_STATE_HOME/tuun/history.jsonl` when it ends.
+//! When plays start and end, and how long they were listened to, comes from [`crate::session`].
+
+use std::{
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::{
+        BufRead,
+        BufReader,
+        ErrorKind as IOE,
+        Write,
+    },
+    path::PathBuf,
+    sync::atomic::Ordering,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::{
+    debug,
+    error,
+    instrument,
+    trace,
+    warn,
+};
+
+use crate::{
+    CONFIG,
+    config::get_state_dir,
+    mpv::SCROBBLED,
+    session,
+    structs::Track,
+};
+
+/// How a play ended
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    /// The track played to the end
+    Natural,
+    /// Something else was played before the track ended
+    Skipped,
+    /// mpv quit during the track
+    Quit,
+}
+
+/// A play of a track
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Play {
+    /// Unix timestamp of when the play started
+    pub started:        u64,
+    pub path:           Option<PathBuf>,
+    pub title:          String,
+    pub artist:         String,
+    pub primary_artist: String,
+    pub album:          String,
+    pub date:           String,
+    /// The track's art url, if it has one other than the fallback
+    #[serde(default)]
+    pub arturl:         Option<String>,
+    /// Track duration in seconds
+    pub duration:       f64,
+    /// Seconds the track was actually listened to, excluding pauses and seeks
+    pub listened:       f64,
+    pub ending:         Ending,
+    pub scrobbled:      bool,
+}
+
+/// Plays listened to for at least this many seconds count, regardless of the track's length
+const COUNTED_LISTEN: f64 = 30.;
+
+impl Play {
+    /// # Description
+    /// Whether the play counts towards stats.
+    ///
+    /// A play counts if it was listened to for 30 seconds or half the track, whichever is shorter.
+    pub fn counts(&self) -> bool { self.listened >= COUNTED_LISTEN.min(self.duration / 2.) }
+}
+
+impl From<&Play> for Track {
+    fn from(play: &Play) -> Self {
+        Self {
+            path: play.path.clone(),
+            title: play.title.clone(),
+            artist: play.artist.clone(),
+            album: play.album.clone(),
+            date: play.date.clone(),
+            arturl: play.arturl.clone().unwrap_or_default(),
+            duration: play.duration,
+            ..Self::default()
+        }
+    }
+}
+
+pub fn history_file() -> Option<PathBuf> { get_state_dir().map(|d| d.join("tuun/history.jsonl")) }
+
+/// # Description
+/// Ends the play in progress and records it.
+///
+/// Plays that were never listened to aren't recorded.
+#[instrument(skip(track))]
+pub async fn finish(ending: Ending, track: &Track) {
+    let Some(session) = session::end().await else {
+        return;
+    };
+
+    if !CONFIG.history.used || session.listened <= 0. || track.is_default() {
+        trace!("Not recording play of '{track}'");
+        return;
+    }
+
+    let play = Play {
+        started: session.started,
+        path: track.path.clone(),
+        title: track.title.clone(),
+        artist: track.artist.clone(),
+        primary_artist: track.get_primary_artist(),
+        album: track.album.clone(),
+        date: track.date.clone(),
+        arturl: (!track.arturl.is_empty() && track.arturl != CONFIG.discord.fallback_art)
+            .then(|| track.arturl.clone()),
+        duration: track.duration,
+        listened: session.listened,
+        ending,
+        scrobbled: SCROBBLED.load(Ordering::Relaxed),
+    };
+
+    match record(&play) {
+        | Ok(()) => debug!("Recorded play of '{track}' ({ending:?})"),
+        | Err(e) => error!("Failed to record play of '{track}': {e:#}"),
+    }
+}
+
+/// Appends a play to the history file
+fn record(play: &Play) -> Result<()> {
+    let path = history_file().context("Couldn't find a state directory")?;
+    let dir = path.parent().context("History path should have a parent")?;
+    fs::create_dir_all(dir)?;
+
+    let mut line = serde_json::to_string(play)?;
+    line.push('\n');
+
+    // A single write keeps lines whole even if something else appends at the same time
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// # Description
+/// Reads every recorded play, oldest first.
+///
+/// Lines that can't be parsed are skipped with a warning.
+pub fn load() -> Result<Vec<Play>> {
+    let path = history_file().context("Couldn't find a state directory")?;
+    let file = match fs::File::open(&path) {
+        | Ok(f) => f,
+        | Err(e) if e.kind() == IOE::NotFound => return Ok(Vec::new()),
+        | Err(e) => return Err(e.into()),
+    };
+
+    let mut plays = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str(&line) {
+            | Ok(play) => plays.push(play),
+            | Err(e) => warn!("Skipping malformed history line {}: {e}", i + 1),
+        }
+    }
+    Ok(plays)
+}
@@ -0,0 +1,205 @@
+// src/index.rs
+//! Logic for the on-disk library index
+//!
+//! The index caches each track's tags, duration, and whether it has art, keyed by path. Entries
+//! are only reparsed when a track's mtime or size changes, so rescans are cheap. It's stored as
+//! JSON at `$XDG_CACHE_HOME/tuun/index.json`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::{
+    debug,
+    info,
+    instrument,
+    trace,
+    warn,
+};
+
+use crate::{
+    config::get_cache_dir,
+    tags::Tags,
+};
+
+/// Bumped whenever the index format changes so stale indices get rebuilt
+const INDEX_VERSION: u32 = 2;
+
+/// A track in the index
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Entry {
+    pub path:     PathBuf,
+    pub mtime:    Option<SystemTime>,
+    pub size:     u64,
+    pub title:    Option<String>,
+    pub artist:   Option<String>,
+    pub album:    Option<String>,
+    pub date:     Option<String>,
+    /// Duration in seconds, if known
+    pub duration: Option<f64>,
+    pub has_art:  bool,
+}
+
+impl Entry {
+    /// # Description
+    /// Parses a track's tags.
+    ///
+    /// Tracks whose tags can't be read get an entry without tags.
+    fn parse(path: &Path, metadata: &fs::Metadata) -> Self {
+        let mut entry = Self {
+            path: path.to_path_buf(),
+            mtime: metadata.modified().ok(),
+            size: metadata.len(),
+            ..Self::default()
+        };
+
+        let tags = match Tags::read(path) {
+            | Ok(tags) => tags,
+            | Err(e) => {
+                debug!("Couldn't read tags from '{}': {e}", path.display());
+                return entry;
+            },
+        };
+
+        entry.artist = tags.artist();
+        entry.title = tags.title;
+        entry.album = tags.album;
+        entry.date = tags.date;
+        entry.duration = tags.duration;
+        entry.has_art = tags.has_picture || tags.arturl.is_some();
+
+        entry
+    }
+
+    /// Whether the entry is still accurate for a file
+    fn is_fresh(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len() && self.mtime == metadata.modified().ok()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Index {
+    version: u32,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Index {
+    fn path() -> Option<PathBuf> { get_cache_dir().map(|d| d.join("tuun/index.json")) }
+
+    /// # Description
+    /// Loads the index from disk.
+    ///
+    /// A missing, unreadable, or outdated index is treated as empty.
+    #[instrument]
+    pub fn load() -> Self {
+        let empty = Self {
+            version: INDEX_VERSION,
+            entries: BTreeMap::new(),
+        };
+
+        let Some(path) = Self::path() else {
+            warn!("Couldn't find a cache directory for the library index");
+            return empty;
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            | Ok(c) => c,
+            | Err(e) => {
+                debug!("Couldn't read library index at '{}': {e}", path.display());
+                return empty;
+            },
+        };
+
+        match serde_json::from_str::<Self>(&contents) {
+            | Ok(index) if index.version == INDEX_VERSION => {
+                debug!(
+                    "Loaded {} entries from the library index",
+                    index.entries.len()
+                );
+                index
+            },
+            | Ok(index) => {
+                info!("Rebuilding library index from version {}", index.version);
+                empty
+            },
+            | Err(e) => {
+                warn!("Library index at '{}' is corrupt: {e}", path.display());
+                empty
+            },
+        }
+    }
+
+    /// Writes the index to disk
+    #[instrument(skip(self))]
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("Couldn't find a cache directory")?;
+        let dir = path.parent().context("Index path should have a parent")?;
+        fs::create_dir_all(dir)?;
+
+        // Write to a temporary file first so an interrupted save can't corrupt the index
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, &path)?;
+
+        debug!(
+            "Saved {} entries to '{}'",
+            self.entries.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// # Description
+    /// Brings the index in line with a set of tracks.
+    ///
+    /// Only new and changed tracks are parsed, and tracks that aren't in the set are forgotten.
+    /// Returns how many tracks were parsed.
+    #[instrument(skip(self, tracks))]
+    pub fn refresh(&mut self, tracks: &[PathBuf]) -> usize {
+        let mut entries = BTreeMap::new();
+        let mut parsed = 0;
+
+        for path in tracks {
+            let Ok(metadata) = fs::metadata(path) else {
+                trace!("Couldn't stat '{}'", path.display());
+                continue;
+            };
+
+            let entry = match self.entries.remove(path) {
+                | Some(entry) if entry.is_fresh(&metadata) => entry,
+                | _ => {
+                    parsed += 1;
+                    trace!("Parsing '{}'", path.display());
+                    Entry::parse(path, &metadata)
+                },
+            };
+            entries.insert(path.clone(), entry);
+        }
+
+        debug!(
+            "Parsed {parsed} tracks and forgot {} while refreshing the library index",
+            self.entries.len()
+        );
+        self.entries = entries;
+        parsed
+    }
+
+    /// Iterates over the entries, sorted by path
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> { self.entries.values() }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+}
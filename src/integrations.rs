@@ -1,16 +1,10 @@
-use std::{
-    sync::Arc,
-    time::{
-        Duration,
-        SystemTime,
-        UNIX_EPOCH,
-    },
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
 };
 
-use anyhow::{
-    Result,
-    bail,
-};
+use anyhow::Result;
 use discord_rich_presence::{
     DiscordIpc,
     activity::{
@@ -21,15 +15,10 @@ use discord_rich_presence::{
     error::Error as DrpErr,
 };
 use permitit::Permit;
-use rustfm_scrobble::{
-    Scrobble,
-    Scrobbler,
-};
 use tokio::time::timeout;
 use tracing::{
     debug,
     error,
-    info,
     instrument,
     warn,
 };
@@ -37,132 +26,9 @@ use tracing::{
 use crate::{
     CONFIG,
     RPC_CLIENT,
-    SCROBBLER,
-    structs::{
-        LastFM,
-        Track,
-    },
+    structs::Track,
 };
 
-#[instrument]
-pub async fn authenticate_lastfm_scrobbler() -> Result<()> {
-    let mut scrobbler_lock = SCROBBLER.lock().await;
-
-    if scrobbler_lock.is_none() {
-        info!("Authenticating lastfm scrobbler...");
-        let lfm = LastFM::new();
-
-        if lfm.apikey.is_empty()
-            || lfm.secret.is_empty()
-            || lfm.username.is_empty()
-            || lfm.password.is_empty()
-        {
-            warn!("Cowardly refusing to authenticate without credentials");
-            bail!("Cowardly refusing to authenticate without credentials");
-        }
-
-        let mut scrobbler = Scrobbler::new(lfm.apikey, lfm.secret);
-        scrobbler.authenticate_with_password(lfm.username, lfm.password)?;
-
-        *scrobbler_lock = Some(Arc::new(scrobbler));
-        drop(scrobbler_lock);
-        info!("Authenticated lastfm scrobbler");
-    } else {
-        debug!("Not authenticating as scrobbler_lock is Some");
-    }
-
-    Ok(())
-}
-
-#[instrument]
-/// Version of [`authenticate_lastfm_scrobbler()`] that doesn't check if the lock is taken
-pub async fn authenticate_lastfm_scrobbler_unchecked() -> Result<()> {
-    let mut scrobbler_lock = SCROBBLER.lock().await;
-
-    info!("Authenticating lastfm scrobbler...");
-    let lfm = LastFM::new();
-
-    if lfm.apikey.is_empty()
-        || lfm.secret.is_empty()
-        || lfm.username.is_empty()
-        || lfm.password.is_empty()
-    {
-        warn!("Cowardly refusing to authenticate without credentials");
-        bail!("Cowardly refusing to authenticate without credentials");
-    }
-
-    let mut scrobbler = Scrobbler::new(lfm.apikey, lfm.secret);
-    scrobbler.authenticate_with_password(lfm.username, lfm.password)?;
-
-    *scrobbler_lock = Some(Arc::new(scrobbler));
-    drop(scrobbler_lock);
-    info!("Authenticated lastfm scrobbler");
-
-    Ok(())
-}
-
-#[instrument(skip(track))]
-pub async fn lastfm_now_playing(track: Track) -> Result<()> {
-    let scrobbler_lock = SCROBBLER.lock().await;
-
-    for att in 1..=3 {
-        if scrobbler_lock.is_none() {
-            warn!("Trying to initialize scrobbler");
-            if let Err(e) = authenticate_lastfm_scrobbler_unchecked().await {
-                error!("Failed to initialize scrobbler: {e}");
-            }
-        } else {
-            debug!("Got scrobbler lock on attempt {att}");
-            break;
-        }
-    }
-
-    let Some(scrobbler) = &*scrobbler_lock else {
-        error!("Scrobbler is not initialized");
-        bail!("Scrobbler is not initialized");
-    };
-
-    let track = Scrobble::new(&track.get_primary_artist(), &track.title, &track.album);
-    let scrobbler = Arc::clone(scrobbler);
-
-    tokio::task::spawn_blocking(move || scrobbler.now_playing(&track)).await??;
-    drop(scrobbler_lock);
-
-    debug!("Set now playing");
-    Ok(())
-}
-
-#[instrument(skip(track))]
-pub async fn lastfm_scrobble(track: Track) -> Result<()> {
-    let scrobbler_lock = SCROBBLER.lock().await;
-
-    for att in 1..=3 {
-        if scrobbler_lock.is_none() {
-            warn!("Trying to initialize scrobbler");
-            if let Err(e) = authenticate_lastfm_scrobbler_unchecked().await {
-                error!("Failed to initialize scrobbler: {e}");
-            }
-        } else {
-            debug!("Got scrobbler lock on attempt {att}");
-            break;
-        }
-    }
-
-    let Some(scrobbler) = &*scrobbler_lock else {
-        error!("Scrobbler is not initialized");
-        bail!("Scrobbler is not initialized");
-    };
-
-    let track = Scrobble::new(&track.get_primary_artist(), &track.title, &track.album);
-    let scrobbler = Arc::clone(scrobbler);
-
-    tokio::task::spawn_blocking(move || scrobbler.scrobble(&track)).await??;
-    drop(scrobbler_lock);
-
-    debug!("Scrobbled");
-    Ok(())
-}
-
 #[instrument(skip(track))]
 pub async fn discord_rpc(track: Track, now_ago: Duration) -> Result<()> {
     if !CONFIG.discord.used {
@@ -232,7 +98,7 @@ fn create_rpc_payload(track: &Track, now_ago: Duration) -> Activity<'_> {
     let start = now
         .duration_since(UNIX_EPOCH)
         .expect("Grandfather paradox or something idk")
-        - now_ago;
+        .saturating_sub(now_ago);
     let end = start + Duration::from_secs_f64(track.duration);
 
     #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
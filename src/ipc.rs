@@ -0,0 +1,374 @@
+// src/ipc.rs
+//! Logic for tuun's own control socket
+//!
+//! Clients send newline-delimited JSON requests like `{"command": "status"}` and receive one
+//! newline-delimited JSON response per request. Every message carries the protocol version, which
+//! is bumped whenever a change would break existing clients.
+//!
+//! Sending `{"command": "subscribe"}` turns the connection into an event stream.
+
+use std::{
+    fs,
+    io::ErrorKind as IOE,
+    sync::{
+        LazyLock,
+        atomic::Ordering,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use permitit::Permit;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use tokio::{
+    io::{
+        AsyncBufReadExt,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::{
+        UnixListener,
+        UnixStream,
+        unix::OwnedWriteHalf,
+    },
+    sync::broadcast::{
+        self,
+        error::RecvError,
+    },
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    instrument,
+    warn,
+};
+
+use crate::{
+    loved,
+    mpv::{
+        self,
+        LOOPED,
+        MUTED,
+        NOW_PLAYING_SET,
+        PAUSED,
+        SCROBBLED,
+        TRACK,
+        VOLUME,
+    },
+    queue::{
+        self,
+        Mode,
+    },
+    scrobbler::{
+        self,
+        ScrobblerStatus,
+        Target,
+    },
+    structs::Track,
+};
+
+pub const SOCK_PATH: &str = "/tmp/tuun/tuunsocket";
+pub const PROTOCOL_VERSION: u32 = 1;
+
+static EVENTS: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| broadcast::channel(64).0);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Get the current track and player state
+    Status,
+    /// Queue tracks
+    Queue {
+        tracks: Vec<String>,
+        #[serde(default)]
+        mode:   Mode,
+    },
+    /// List upcoming queued tracks
+    QueueList,
+    /// Remove the queued track at a position
+    QueueRemove { position: usize },
+    /// Move the queued track at one position to another
+    QueueMove { from: usize, to: usize },
+    /// Remove every queued track
+    QueueClear,
+    /// Skip to the next track
+    Skip,
+    /// Love the current track
+    Love,
+    /// Unlove the current track
+    Unlove,
+    /// Stream events for the rest of the connection
+    Subscribe,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub version: u32,
+    pub ok:      bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data:    Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:   Option<String>,
+}
+
+impl Response {
+    const fn ok(data: Option<Value>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok: true,
+            data,
+            error: None,
+        }
+    }
+
+    const fn err(error: String) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            ok:      false,
+            data:    None,
+            error:   Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The current track's metadata changed
+    Track {
+        track: Track,
+    },
+    Pause {
+        paused: bool,
+    },
+    Mute {
+        muted: bool,
+    },
+    Loop {
+        looped: bool,
+    },
+    Volume {
+        volume: u32,
+    },
+    Shuffle {
+        shuffled: bool,
+    },
+    /// The position jumped, such as after seeking
+    Seeked {
+        position: f64,
+    },
+    /// Now playing status was pushed for the current track
+    NowPlaying {
+        track: Track,
+    },
+    /// The current track was scrobbled to a service
+    Scrobbled {
+        track:  Track,
+        target: Target,
+    },
+    Queued {
+        tracks: Vec<String>,
+    },
+    /// A track was loved or unloved
+    Loved {
+        track: Track,
+        loved: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct EventMessage<'e> {
+    version: u32,
+    #[serde(flatten)]
+    event:   &'e Event,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub track:          Track,
+    pub primary_artist: String,
+    pub paused:         bool,
+    pub muted:          bool,
+    pub looped:         bool,
+    pub volume:         u32,
+    pub now_playing:    bool,
+    pub scrobbled:      bool,
+    pub loved:          bool,
+    pub scrobblers:     Vec<ScrobblerStatus>,
+}
+
+impl Status {
+    pub async fn current() -> Self {
+        let track = TRACK.lock().await.clone();
+        Self {
+            primary_artist: track.get_primary_artist(),
+            loved: loved::is_loved(&track),
+            track,
+            paused: PAUSED.load(Ordering::Relaxed),
+            muted: MUTED.load(Ordering::Relaxed),
+            looped: LOOPED.load(Ordering::Relaxed),
+            volume: VOLUME.load(Ordering::Relaxed),
+            now_playing: NOW_PLAYING_SET.load(Ordering::Relaxed),
+            scrobbled: SCROBBLED.load(Ordering::Relaxed),
+            scrobblers: scrobbler::statuses(),
+        }
+    }
+}
+
+/// Sends an event to all subscribers
+pub fn emit(event: Event) {
+    // Sending only fails when nobody is subscribed, which is fine
+    let _ = EVENTS.send(event);
+}
+
+/// Receives all events sent after subscribing
+pub fn subscribe() -> broadcast::Receiver<Event> { EVENTS.subscribe() }
+
+/// # Description
+/// Serves tuun's control socket forever
+#[instrument]
+pub async fn serve() -> Result<()> {
+    fs::remove_file(SOCK_PATH).permit(|e| e.kind() == IOE::NotFound)?;
+    let listener = UnixListener::bind(SOCK_PATH)?;
+    info!("Listening on {SOCK_PATH}");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        debug!("Accepted control socket client");
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream).await {
+                warn!("Control socket client errored: {e:#}");
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let response = match serde_json::from_str::<Request>(&line) {
+            | Ok(Request::Subscribe) => {
+                write_message(&mut writer, &Response::ok(None)).await?;
+                return stream_events(writer).await;
+            },
+            | Ok(request) => {
+                debug!("Handling request: {request:?}");
+                match handle_request(request).await {
+                    | Ok(data) => Response::ok(data),
+                    | Err(e) => {
+                        error!("Failed to handle request: {e:#}");
+                        Response::err(format!("{e:#}"))
+                    },
+                }
+            },
+            | Err(e) => Response::err(format!("Invalid request: {e}")),
+        };
+
+        write_message(&mut writer, &response).await?;
+    }
+
+    Ok(())
+}
+
+async fn handle_request(request: Request) -> Result<Option<Value>> {
+    match request {
+        | Request::Status => Ok(Some(serde_json::to_value(Status::current().await)?)),
+        | Request::Queue { tracks, mode } => {
+            queue::add(&tracks, mode).await?;
+            Ok(None)
+        },
+        | Request::QueueList => Ok(Some(serde_json::to_value(queue::list().await?)?)),
+        | Request::QueueRemove { position } => {
+            queue::remove(position).await?;
+            Ok(None)
+        },
+        | Request::QueueMove { from, to } => {
+            queue::reorder(from, to).await?;
+            Ok(None)
+        },
+        | Request::QueueClear => {
+            queue::clear().await?;
+            Ok(None)
+        },
+        | Request::Skip => {
+            mpv::send_command(r#"{ "command": ["playlist-next"] }"#).await?;
+            Ok(None)
+        },
+        | Request::Love | Request::Unlove => {
+            let track = TRACK.lock().await.clone();
+            if track.is_default() {
+                bail!("Nothing is playing");
+            }
+            loved::set(&track, matches!(request, Request::Love)).await?;
+            Ok(Some(serde_json::to_value(track)?))
+        },
+        | Request::Subscribe => unreachable!("Subscriptions are handled by the caller"),
+    }
+}
+
+async fn stream_events(mut writer: OwnedWriteHalf) -> Result<()> {
+    let mut events = subscribe();
+    debug!("Client subscribed to events");
+
+    loop {
+        match events.recv().await {
+            | Ok(event) => {
+                let message = EventMessage {
+                    version: PROTOCOL_VERSION,
+                    event:   &event,
+                };
+                write_message(&mut writer, &message).await?;
+            },
+            | Err(RecvError::Lagged(n)) => warn!("Subscriber lagged behind by {n} events"),
+            | Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn write_message(
+    writer: &mut OwnedWriteHalf,
+    message: &(impl Serialize + Sync),
+) -> Result<()> {
+    let mut bytes = serde_json::to_vec(message)?;
+    bytes.push(b'\n');
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// # Description
+/// Sends a request to the running instance's control socket and returns the response data
+pub async fn request(request: &Request) -> Result<Option<Value>> {
+    let stream = UnixStream::connect(SOCK_PATH).await?;
+    let (reader, mut writer) = stream.into_split();
+    write_message(&mut writer, request).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    let response: Response =
+        serde_json::from_str(&line).context("Invalid response from control socket")?;
+
+    if response.version != PROTOCOL_VERSION {
+        bail!(
+            "Protocol version mismatch (running instance uses {}, expected {PROTOCOL_VERSION})",
+            response.version
+        );
+    }
+
+    if !response.ok {
+        bail!(response.error.unwrap_or_else(|| "Unknown error".to_owned()));
+    }
+
+    Ok(response.data)
+}
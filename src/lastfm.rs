@@ -0,0 +1,618 @@
+// src/lastfm.rs
+//! A minimal client for the Last.fm scrobbling API
+//!
+//! `rustfm-scrobble` can't send album artists, track numbers, or durations, so tuun signs and sends
+//! its own requests. Libre.fm and other GNU FM instances speak the same API at a different root.
+//! Requests block, so they should be made from `spawn_blocking`.
+//!
+//! `tuun lastfm login` authorizes tuun through the Last.fm website and keeps the resulting session
+//! key in `$XDG_STATE_HOME/tuun/lastfm_session.json`, so the account password needn't be in the
+//! config.
+
+use std::{
+    fmt,
+    fs::{
+        self,
+        OpenOptions,
+    },
+    io::{
+        self,
+        ErrorKind as IOE,
+        Write,
+    },
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    sync::{
+        Arc,
+        LazyLock,
+        Mutex,
+        PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use tracing::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use ureq::Agent;
+
+use crate::{
+    CONFIG,
+    args::LastfmAction,
+    config::get_state_dir,
+    scrobbler::{
+        Scrobble,
+        Scrobbler,
+        Target,
+    },
+};
+
+pub const API_ROOT: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Where users grant tuun access to their account
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+/// The most scrobbles Last.fm accepts in one request
+pub const BATCH_SIZE: usize = 50;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static AGENT: LazyLock<Agent> = LazyLock::new(|| {
+    Agent::config_builder()
+        // Last.fm explains its errors in the body, so don't throw it away
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into()
+});
+
+/// Last.fm's error code for an invalid or expired session key
+const INVALID_SESSION: u64 = 9;
+
+/// Last.fm's error code for a token the user hasn't authorized yet
+const UNAUTHORIZED_TOKEN: u64 = 14;
+
+/// An error reported by the API itself, rather than by the connection
+#[derive(Debug)]
+pub struct ApiError {
+    pub code:    u64,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Returns a scrobble's parameters, suffixed with `[i]` if it's part of a batch
+fn params(scrobble: &Scrobble, index: Option<usize>) -> Vec<(String, String)> {
+    let key = |k: &str| index.map_or_else(|| k.to_owned(), |i| format!("{k}[{i}]"));
+
+    let mut params = vec![
+        (key("artist"), scrobble.artist.clone()),
+        (key("track"), scrobble.title.clone()),
+    ];
+    if let Some(album) = scrobble.known_album() {
+        params.push((key("album"), album.to_owned()));
+    }
+    if let Some(album_artist) = &scrobble.album_artist {
+        params.push((key("albumArtist"), album_artist.clone()));
+    }
+    if let Some(track_number) = scrobble.track_number {
+        params.push((key("trackNumber"), track_number.to_string()));
+    }
+    if let Some(duration) = scrobble.duration {
+        params.push((key("duration"), duration.to_string()));
+    }
+    params
+}
+
+/// Returns the parameters for scrobbling a batch of tracks
+fn batch_params(scrobbles: &[Scrobble]) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    for (i, scrobble) in scrobbles.iter().enumerate() {
+        params.extend(self::params(scrobble, Some(i)));
+        params.push((format!("timestamp[{i}]"), scrobble.timestamp.to_string()));
+    }
+    params
+}
+
+/// # Description
+/// Returns the API signature for a request's parameters.
+///
+/// The signature is the md5 of every parameter's name and value, sorted by name, followed by the
+/// secret. `format` and `callback` aren't signed.
+fn sign(params: &[(String, String)], secret: &str) -> String {
+    let mut params = params
+        .iter()
+        .filter(|(k, _)| k != "format" && k != "callback")
+        .collect::<Vec<_>>();
+    params.sort();
+
+    let mut signature = params.iter().fold(String::new(), |mut s, (k, v)| {
+        s.push_str(k);
+        s.push_str(v);
+        s
+    });
+    signature.push_str(secret);
+    format!("{:x}", md5::compute(signature))
+}
+
+/// An authenticated session
+#[derive(Debug)]
+pub struct Client {
+    api_root:    String,
+    apikey:      String,
+    secret:      String,
+    session_key: String,
+}
+
+impl Client {
+    /// Resumes a session from its key
+    pub fn new(api_root: &str, apikey: &str, secret: &str, session_key: &str) -> Self {
+        Self {
+            api_root:    api_root.to_owned(),
+            apikey:      apikey.to_owned(),
+            secret:      secret.to_owned(),
+            session_key: session_key.to_owned(),
+        }
+    }
+
+    /// Starts a session with a username and password
+    pub fn authenticate(
+        api_root: &str,
+        apikey: &str,
+        secret: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<Self> {
+        let mut client = Self::new(api_root, apikey, secret, "");
+
+        let response = client.call("auth.getMobileSession", vec![
+            ("username".to_owned(), username.to_owned()),
+            ("password".to_owned(), password.to_owned()),
+        ])?;
+        response
+            .pointer("/session/key")
+            .and_then(Value::as_str)
+            .context("Last.fm didn't return a session key")?
+            .clone_into(&mut client.session_key);
+
+        Ok(client)
+    }
+
+    /// Requests a token for the user to authorize on the website
+    fn request_token(&self) -> Result<String> {
+        let response = self.call("auth.getToken", Vec::new())?;
+        response
+            .get("token")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned)
+            .context("Last.fm didn't return a token")
+    }
+
+    /// Trades an authorized token for a session
+    fn session(&self, token: &str) -> Result<Session> {
+        let response = self.call("auth.getSession", vec![(
+            "token".to_owned(),
+            token.to_owned(),
+        )])?;
+        let session = response
+            .get("session")
+            .context("Last.fm didn't return a session")?;
+        Ok(serde_json::from_value(session.clone())?)
+    }
+
+    pub fn now_playing(&self, scrobble: &Scrobble) -> Result<()> {
+        self.call("track.updateNowPlaying", params(scrobble, None))?;
+        Ok(())
+    }
+
+    pub fn love(&self, scrobble: &Scrobble, loved: bool) -> Result<()> {
+        let method = if loved { "track.love" } else { "track.unlove" };
+        self.call(method, vec![
+            ("artist".to_owned(), scrobble.artist.clone()),
+            ("track".to_owned(), scrobble.title.clone()),
+        ])?;
+        Ok(())
+    }
+
+    /// # Description
+    /// Scrobbles up to [`BATCH_SIZE`] tracks at once.
+    ///
+    /// Scrobbles Last.fm ignores (for being too old, for instance) are logged rather than treated as
+    /// errors, since retrying them wouldn't help.
+    pub fn scrobble(&self, scrobbles: &[Scrobble]) -> Result<()> {
+        if scrobbles.len() > BATCH_SIZE {
+            bail!("Can't scrobble more than {BATCH_SIZE} tracks at once");
+        }
+
+        let response = self.call("track.scrobble", batch_params(scrobbles))?;
+        let ignored = response
+            .pointer("/scrobbles/@attr/ignored")
+            .and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()))
+            .unwrap_or(0);
+        if ignored > 0 {
+            warn!("Last.fm ignored {ignored} of {} scrobbles", scrobbles.len());
+        }
+        Ok(())
+    }
+
+    /// Adds the method, credentials, and signature to a request's parameters
+    fn signed(&self, method: &str, mut params: Vec<(String, String)>) -> Vec<(String, String)> {
+        params.push(("method".to_owned(), method.to_owned()));
+        params.push(("api_key".to_owned(), self.apikey.clone()));
+        if !self.session_key.is_empty() {
+            params.push(("sk".to_owned(), self.session_key.clone()));
+        }
+
+        params.sort();
+        params.push(("api_sig".to_owned(), sign(&params, &self.secret)));
+        params.push(("format".to_owned(), "json".to_owned()));
+        params
+    }
+
+    /// Signs and sends a request, returning the response if the API didn't report an error
+    fn call(&self, method: &str, params: Vec<(String, String)>) -> Result<Value> {
+        let params = self.signed(method, params);
+
+        debug!("Calling {method}");
+        let body = AGENT
+            .post(&self.api_root)
+            .send_form(params.iter().map(|(k, v)| (k, v)))?
+            .body_mut()
+            .read_to_string()?;
+        let response = serde_json::from_str::<Value>(&body)
+            .with_context(|| format!("Got invalid JSON for {method}"))?;
+
+        if let Some(code) = response.get("error").and_then(Value::as_u64) {
+            let message = response
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("no message")
+                .to_owned();
+            return Err(ApiError { code, message })
+                .with_context(|| format!("Failed to call {method}"));
+        }
+        Ok(response)
+    }
+}
+
+/// A Last.fm session from `tuun lastfm login`
+#[derive(Serialize, Deserialize, Debug)]
+struct Session {
+    /// The username the session belongs to
+    name: String,
+    key:  String,
+}
+
+fn session_file() -> Option<PathBuf> { get_state_dir().map(|d| d.join("tuun/lastfm_session.json")) }
+
+/// Reads the stored session, if there is one
+fn load_session() -> Option<Session> {
+    let contents = fs::read_to_string(session_file()?).ok()?;
+    match serde_json::from_str(&contents) {
+        | Ok(session) => Some(session),
+        | Err(e) => {
+            warn!("Ignoring unreadable Last.fm session: {e}");
+            None
+        },
+    }
+}
+
+/// Stores a session where only the current user can read it
+fn save_session(session: &Session) -> Result<PathBuf> {
+    let path = session_file().context("Couldn't find a state directory")?;
+    let dir = path.parent().context("Session path should have a parent")?;
+    fs::create_dir_all(dir)?;
+
+    // Create the temporary file with restrictive permissions from the start, rather than
+    // tightening them after the key is already on disk
+    let tmp = path.with_extension("json.tmp");
+    let _ = fs::remove_file(&tmp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("Failed to create '{}'", tmp.display()))?;
+    file.write_all(serde_json::to_string(session)?.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+/// # Description
+/// Runs `tuun lastfm`, returning an exit code.
+pub fn run(action: &LastfmAction) -> i32 {
+    let result = match action {
+        | LastfmAction::Login => login(),
+        | LastfmAction::Logout => logout(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("tuun: {e:#}");
+        return 1;
+    }
+    0
+}
+
+/// # Description
+/// Walks the user through Last.fm's web authentication and stores the session key.
+///
+/// Only the API key and secret are needed from the config.
+fn login() -> Result<()> {
+    let c = &CONFIG.lastfm;
+    if c.apikey.is_empty() || c.secret.is_empty() {
+        bail!("Set apikey and secret (or apikey_cmd and secret_cmd) under [lastfm] first");
+    }
+
+    let client = Client::new(API_ROOT, &c.apikey, &c.secret, "");
+    let token = client.request_token()?;
+
+    println!("Allow tuun to access your Last.fm account here:");
+    println!("    {AUTH_URL}?api_key={}&token={token}", c.apikey);
+
+    let session = loop {
+        print!("Press Enter once you have allowed access... ");
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut String::new())? == 0 {
+            bail!("Gave up waiting for authorization");
+        }
+
+        match client.session(&token) {
+            | Ok(session) => break session,
+            | Err(e)
+                if e.downcast_ref::<ApiError>()
+                    .is_some_and(|e| e.code == UNAUTHORIZED_TOKEN) =>
+            {
+                println!("tuun hasn't been authorized yet");
+            },
+            | Err(e) => return Err(e),
+        }
+    };
+
+    let path = save_session(&session)?;
+    println!("Logged in as {}", session.name);
+    println!("The session key is stored at '{}'", path.display());
+    Ok(())
+}
+
+fn logout() -> Result<()> {
+    let path = session_file().context("Couldn't find a state directory")?;
+    match fs::remove_file(&path) {
+        | Ok(()) => println!("Forgot the Last.fm session"),
+        | Err(e) if e.kind() == IOE::NotFound => println!("Not logged in to Last.fm"),
+        | Err(e) => return Err(e).with_context(|| format!("Failed to remove '{}'", path.display())),
+    }
+    Ok(())
+}
+
+/// A scrobbler for Last.fm or a GNU FM instance like Libre.fm
+///
+/// Authenticates on first use, and again whenever the session turns out to have expired. Last.fm
+/// prefers a session stored by `tuun lastfm login` over the username and password.
+pub struct Audioscrobbler {
+    target:   Target,
+    api_root: String,
+    apikey:   String,
+    secret:   String,
+    username: String,
+    password: String,
+    client:   Mutex<Option<Arc<Client>>>,
+}
+
+impl Audioscrobbler {
+    pub fn new(
+        target: Target,
+        api_root: &str,
+        apikey: &str,
+        secret: &str,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        Self {
+            target,
+            api_root: api_root.to_owned(),
+            apikey: apikey.to_owned(),
+            secret: secret.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            client: Mutex::new(None),
+        }
+    }
+
+    /// Returns the current session, authenticating if there isn't one
+    fn client(&self) -> Result<Arc<Client>> {
+        // Holding the lock while authenticating keeps concurrent calls from each starting a session
+        let mut client = self.client.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(client) = &*client {
+            return Ok(Arc::clone(client));
+        }
+
+        if self.apikey.is_empty() || self.secret.is_empty() {
+            warn!("Cowardly refusing to authenticate without an API key and secret");
+            bail!("Cowardly refusing to authenticate without an API key and secret");
+        }
+
+        if self.target == Target::LastFM
+            && let Some(session) = load_session()
+        {
+            info!("Using the stored Last.fm session for {}", session.name);
+            let resumed = Arc::new(Client::new(
+                &self.api_root,
+                &self.apikey,
+                &self.secret,
+                &session.key,
+            ));
+            *client = Some(Arc::clone(&resumed));
+            drop(client);
+            return Ok(resumed);
+        }
+
+        if self.username.is_empty() || self.password.is_empty() {
+            warn!("Cowardly refusing to authenticate without credentials");
+            if self.target == Target::LastFM {
+                bail!(
+                    "Cowardly refusing to authenticate without credentials; try `tuun lastfm login`"
+                );
+            }
+            bail!("Cowardly refusing to authenticate without credentials");
+        }
+
+        info!("Authenticating with {}...", self.target);
+        let authenticated = Arc::new(Client::authenticate(
+            &self.api_root,
+            &self.apikey,
+            &self.secret,
+            &self.username,
+            &self.password,
+        )?);
+        *client = Some(Arc::clone(&authenticated));
+        drop(client);
+        info!("Authenticated with {}", self.target);
+
+        Ok(authenticated)
+    }
+
+    /// Runs a call, forgetting the session if it was rejected so the next call authenticates
+    fn with_client<T>(&self, f: impl FnOnce(&Client) -> Result<T>) -> Result<T> {
+        let result = f(self.client()?.as_ref());
+
+        if let Err(e) = &result
+            && e.downcast_ref::<ApiError>()
+                .is_some_and(|e| e.code == INVALID_SESSION)
+        {
+            if self.target == Target::LastFM && load_session().is_some() {
+                error!("Last.fm rejected the stored session; run `tuun lastfm login` again");
+            } else {
+                warn!("{} session expired", self.target);
+            }
+            *self.client.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        }
+        result
+    }
+}
+
+impl Scrobbler for Audioscrobbler {
+    fn batch_size(&self) -> usize { BATCH_SIZE }
+
+    fn connect(&self) -> Result<()> { self.client().map(|_| ()) }
+
+    fn now_playing(&self, scrobble: &Scrobble) -> Result<()> {
+        self.with_client(|c| c.now_playing(scrobble))
+    }
+
+    fn scrobble(&self, scrobbles: &[Scrobble]) -> Result<()> {
+        self.with_client(|c| c.scrobble(scrobbles))
+    }
+
+    fn can_love(&self) -> bool { true }
+
+    fn love(&self, scrobble: &Scrobble, loved: bool) -> Result<()> {
+        self.with_client(|c| c.love(scrobble, loved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrobble(artist: &str, title: &str, album: &str, timestamp: u64) -> Scrobble {
+        Scrobble {
+            artist: artist.to_owned(),
+            title: title.to_owned(),
+            album: album.to_owned(),
+            album_artist: None,
+            track_number: None,
+            duration: None,
+            timestamp,
+        }
+    }
+
+    fn pairs(params: &[(&str, &str)]) -> Vec<(String, String)> {
+        params
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn signature_sorts_params_and_skips_format_and_callback() {
+        let params = pairs(&[
+            ("track", "T"),
+            ("format", "json"),
+            ("artist", "A"),
+            ("callback", "cb"),
+            ("api_key", "key"),
+        ]);
+        let expected = format!("{:x}", md5::compute("api_keykeyartistAtrackTsecret"));
+        assert_eq!(sign(&params, "secret"), expected);
+    }
+
+    #[test]
+    fn requests_are_signed_with_method_and_session() {
+        let client = Client {
+            api_root:    API_ROOT.to_owned(),
+            apikey:      "key".to_owned(),
+            secret:      "secret".to_owned(),
+            session_key: "session".to_owned(),
+        };
+        let params = client.signed("track.love", pairs(&[("track", "T"), ("artist", "A")]));
+
+        let expected = format!(
+            "{:x}",
+            md5::compute("api_keykeyartistAmethodtrack.lovesksessiontrackTsecret")
+        );
+        assert_eq!(
+            params,
+            pairs(&[
+                ("api_key", "key"),
+                ("artist", "A"),
+                ("method", "track.love"),
+                ("sk", "session"),
+                ("track", "T"),
+                ("api_sig", &expected),
+                ("format", "json"),
+            ])
+        );
+    }
+
+    #[test]
+    fn batches_index_every_param() {
+        let params = batch_params(&[
+            scrobble("A", "One", "Album", 100),
+            scrobble("B", "Two", "<Unknown album>", 200),
+        ]);
+        assert_eq!(
+            params,
+            pairs(&[
+                ("artist[0]", "A"),
+                ("track[0]", "One"),
+                ("album[0]", "Album"),
+                ("timestamp[0]", "100"),
+                ("artist[1]", "B"),
+                ("track[1]", "Two"),
+                ("timestamp[1]", "200"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,489 @@
+// src/library.rs
+//! Logic for finding tracks in the music library
+//!
+//! The library is made up of one or more roots, configured with `[[library]]`. If none are
+//! configured, `general.music_dir` is the only root.
+//!
+//! Directories may contain a `.tuunignore` file with gitignore-style patterns. Patterns are
+//! relative to the directory containing the file, and later patterns override earlier ones,
+//! including those from parent directories.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use anyhow::{
+    Result,
+    bail,
+};
+use tracing::{
+    debug,
+    info,
+    instrument,
+    trace,
+    warn,
+};
+
+use crate::{
+    CONFIG,
+    config::LibraryConfig,
+    index::Index,
+};
+
+pub const IGNORE_FILE: &str = ".tuunignore";
+
+/// How to scan a library root
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions {
+    pub recursive:       bool,
+    pub follow_symlinks: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            recursive:       CONFIG.general.recursive,
+            follow_symlinks: CONFIG.general.follow_symlinks,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn for_root(root: &LibraryConfig) -> Self {
+        let default = Self::default();
+        Self {
+            recursive:       root.recursive.unwrap_or(default.recursive),
+            follow_symlinks: root.follow_symlinks.unwrap_or(default.follow_symlinks),
+        }
+    }
+}
+
+/// A library root with its options resolved
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub path:     PathBuf,
+    pub options:  ScanOptions,
+    pub in_all:   bool,
+    pub required: bool,
+}
+
+/// # Description
+/// Returns the configured library roots.
+///
+/// Falls back to `general.music_dir` if no roots are configured.
+pub fn roots() -> Vec<Root> {
+    if CONFIG.library.is_empty() {
+        return vec![Root {
+            path:     PathBuf::from(&CONFIG.general.music_dir),
+            options:  ScanOptions::default(),
+            in_all:   true,
+            required: true,
+        }];
+    }
+
+    CONFIG
+        .library
+        .iter()
+        .filter(|r| {
+            if r.path.is_empty() {
+                warn!("Ignoring library root without a path");
+            }
+            !r.path.is_empty()
+        })
+        .map(|r| Root {
+            path:     PathBuf::from(&r.path),
+            options:  ScanOptions::for_root(r),
+            in_all:   r.in_all,
+            required: r.required,
+        })
+        .collect()
+}
+
+/// # Description
+/// Checks that every library root exists.
+///
+/// Missing roots are an error if they're required, and a warning otherwise.
+pub fn check_roots() -> Result<()> {
+    for root in roots() {
+        if root.path.is_dir() {
+            continue;
+        }
+
+        if root.required {
+            bail!("Library root '{}' does not exist!", root.path.display());
+        }
+        warn!(
+            "Library root '{}' does not exist, skipping it",
+            root.path.display()
+        );
+    }
+    Ok(())
+}
+
+/// # Description
+/// Finds all audio files under the library roots that exist and match a filter.
+///
+/// Tracks found under more than one root are only listed once. Results are sorted by path.
+pub fn scan_roots(filter: impl Fn(&Root) -> bool) -> Vec<PathBuf> {
+    let mut tracks = roots()
+        .into_iter()
+        .filter(|r| r.path.is_dir() && filter(r))
+        .flat_map(|r| scan(&r.path, r.options))
+        .collect::<Vec<_>>();
+
+    tracks.sort();
+    tracks.dedup();
+    tracks
+}
+
+/// Whether a track is under a library root that's included in `all.tpl`
+pub fn in_all(path: &Path) -> bool {
+    roots()
+        .iter()
+        .any(|r| r.in_all && path.starts_with(&r.path))
+}
+
+/// # Description
+/// Loads the library index and rescans the library roots, reparsing only changed tracks.
+///
+/// The refreshed index is saved before it's returned.
+#[instrument]
+pub fn index() -> Index {
+    let mut index = Index::load();
+    let parsed = index.refresh(&scan_roots(|_| true));
+    info!("Indexed {} tracks ({parsed} parsed)", index.len());
+
+    if let Err(e) = index.save() {
+        warn!("Failed to save the library index: {e:#}");
+    }
+    index
+}
+
+#[derive(Debug)]
+struct Pattern {
+    glob:     Vec<char>,
+    negated:  bool,
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole relative path rather than just the name
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        // Trailing spaces are ignored unless escaped
+        let trimmed = line.trim_end();
+        let line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+            &line[..=trimmed.len()]
+        } else {
+            trimmed
+        };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            | Some(rest) => (true, rest),
+            | None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            | Some(rest) => (true, rest),
+            | None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            glob: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let text = if self.anchored {
+            relative
+        } else {
+            relative.rsplit('/').next().unwrap_or(relative)
+        };
+        glob(&self.glob, &text.chars().collect::<Vec<_>>())
+    }
+}
+
+/// Patterns from one `.tuunignore`, along with the directory they're relative to
+#[derive(Debug)]
+struct IgnoreFile {
+    base:     PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreFile {
+    fn read(dir: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(dir.join(IGNORE_FILE)).ok()?;
+        let patterns = contents
+            .lines()
+            .filter_map(Pattern::parse)
+            .collect::<Vec<_>>();
+        debug!(
+            "Read {} patterns from {}",
+            patterns.len(),
+            dir.join(IGNORE_FILE).display()
+        );
+
+        Some(Self {
+            base: dir.to_path_buf(),
+            patterns,
+        })
+    }
+}
+
+/// Whether a path is ignored, with the last matching pattern winning
+fn is_ignored(ignore_files: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in ignore_files {
+        let Ok(relative) = path.strip_prefix(&file.base) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy();
+
+        for pattern in &file.patterns {
+            if pattern.matches(&relative, is_dir) {
+                ignored = !pattern.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// # Description
+/// Matches gitignore-style globs.
+///
+/// `*` and `?` don't match `/`, `**` matches across directories, `[...]` matches a class of
+/// characters, and `\` matches the next character literally.
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        | [] => text.is_empty(),
+        | ['*', '*', rest @ ..] => {
+            // `**/` matches zero or more whole directories
+            if let ['/', rest @ ..] = rest {
+                return (0..=text.len())
+                    .filter(|&i| i == 0 || text[i - 1] == '/')
+                    .any(|i| glob(rest, &text[i..]));
+            }
+            (0..=text.len()).any(|i| glob(rest, &text[i..]))
+        },
+        | ['*', rest @ ..] => {
+            let segment = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob(rest, &text[i..]))
+        },
+        | ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob(rest, &text[1..]),
+        | ['[', rest @ ..] => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                // An unclosed class is just a literal `[`
+                return text.first() == Some(&'[') && glob(rest, &text[1..]);
+            };
+            let Some(&c) = text.first() else {
+                return false;
+            };
+
+            let (negated, class) = match &rest[..end] {
+                | ['!' | '^', class @ ..] => (true, class),
+                | class => (false, class),
+            };
+            let in_class = class.iter().enumerate().any(|(i, &m)| {
+                if class.get(i + 1) == Some(&'-')
+                    && let Some(&hi) = class.get(i + 2)
+                {
+                    return (m..=hi).contains(&c);
+                }
+                m == c
+            });
+
+            c != '/' && in_class != negated && glob(&rest[end + 1..], &text[1..])
+        },
+        | ['\\', p, rest @ ..] | [p, rest @ ..] => {
+            text.first() == Some(p) && glob(rest, &text[1..])
+        },
+    }
+}
+
+/// Whether a path has one of the configured audio extensions
+pub fn is_audio(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        CONFIG
+            .general
+            .audio_extensions
+            .iter()
+            .any(|a| ext.eq_ignore_ascii_case(a))
+    })
+}
+
+/// # Description
+/// Finds all audio files under a library root, respecting `.tuunignore` files.
+///
+/// Results are sorted by path.
+#[instrument]
+pub fn scan(root: &Path, options: ScanOptions) -> Vec<PathBuf> {
+    let mut tracks = Vec::new();
+    let mut ignores = Vec::new();
+    let mut visited = HashSet::new();
+    walk(root, options, &mut ignores, &mut visited, &mut tracks);
+
+    tracks.sort();
+    debug!("Found {} tracks in {}", tracks.len(), root.display());
+    tracks
+}
+
+fn walk(
+    dir: &Path,
+    options: ScanOptions,
+    ignores: &mut Vec<IgnoreFile>,
+    visited: &mut HashSet<PathBuf>,
+    tracks: &mut Vec<PathBuf>,
+) {
+    // Guard against symlink loops
+    if let Ok(canonical) = dir.canonicalize()
+        && !visited.insert(canonical)
+    {
+        trace!("Already visited {}", dir.display());
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        | Ok(entries) => entries,
+        | Err(e) => {
+            warn!("Failed to read directory '{}': {e}", dir.display());
+            return;
+        },
+    };
+
+    let pushed = IgnoreFile::read(dir).map(|i| ignores.push(i)).is_some();
+
+    for entry in entries.map_while(Result::ok) {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        let (is_dir, is_file) = if file_type.is_symlink() {
+            if !options.follow_symlinks {
+                trace!("Not following symlink {}", path.display());
+                continue;
+            }
+            (path.is_dir(), path.is_file())
+        } else {
+            (file_type.is_dir(), file_type.is_file())
+        };
+
+        if is_ignored(ignores, &path, is_dir) {
+            trace!("Ignoring {}", path.display());
+            continue;
+        }
+
+        if is_dir && options.recursive {
+            walk(&path, options, ignores, visited, tracks);
+        } else if is_file && is_audio(&path) {
+            tracks.push(path);
+        }
+    }
+
+    if pushed {
+        ignores.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a pattern ignores a path relative to its `.tuunignore`
+    fn ignores(pattern: &str, relative: &str, is_dir: bool) -> bool {
+        let file = IgnoreFile {
+            base:     PathBuf::from("/music"),
+            patterns: pattern.lines().filter_map(Pattern::parse).collect(),
+        };
+        is_ignored(&[file], &Path::new("/music").join(relative), is_dir)
+    }
+
+    #[test]
+    fn patterns_match_like_gitignore() {
+        #[rustfmt::skip]
+        let cases = [
+            // Unanchored patterns match the name at any depth
+            ("*.flac",         "a.flac",             false, true),
+            ("*.flac",         "artist/album/a.flac", false, true),
+            ("*.flac",         "a.flac.bak",         false, false),
+            ("live",           "artist/live",        true,  true),
+            // Anchored patterns match the whole relative path
+            ("/live",          "live",               true,  true),
+            ("/live",          "artist/live",        true,  false),
+            ("artist/live",    "artist/live",        true,  true),
+            ("artist/live",    "other/artist/live",  true,  false),
+            ("artist/*.flac",  "artist/album/a.flac", false, false),
+            // Leading, middle, and trailing `**`
+            ("**/live",        "live",               true,  true),
+            ("**/live",        "a/b/live",           true,  true),
+            ("**/live",        "a/blive",            true,  false),
+            ("a/**/b",         "a/b",                true,  true),
+            ("a/**/b",         "a/x/y/b",            true,  true),
+            ("a/**/b",         "a/xb",               true,  false),
+            ("demos/**",       "demos/a/b.flac",     false, true),
+            ("demos/**",       "demos",              true,  false),
+            // Negation overrides an earlier match, and a later match overrides it again
+            ("*.flac\n!keep.flac",          "keep.flac", false, false),
+            ("*.flac\n!keep.flac",          "drop.flac", false, true),
+            ("*.flac\n!keep.flac\nkeep*",   "keep.flac", false, true),
+            // A trailing `/` only matches directories
+            ("live/",          "live",               true,  true),
+            ("live/",          "live",               false, false),
+            // `?` and character classes, none of which match `/`
+            ("track?.flac",    "track1.flac",        false, true),
+            ("track?.flac",    "track10.flac",       false, false),
+            ("a?b",            "a/b",                false, false),
+            ("[a-c]*",         "beta",               false, true),
+            ("[a-c]*",         "delta",              false, false),
+            ("[!x]*",          "yes",                false, true),
+            ("[!x]*",          "xno",                false, false),
+            ("[^x]*",          "xno",                false, false),
+            ("[abc",           "[abc",               false, true),
+            // Escaped characters match literally
+            ("\\#notes",       "#notes",             false, true),
+            ("\\!important",   "!important",         false, true),
+            ("\\*",            "*",                  false, true),
+            ("\\*",            "a",                  false, false),
+            ("what\\?",        "what?",              false, true),
+            ("what\\?",        "whats",              false, false),
+            ("\\[live]",       "[live]",             false, true),
+            ("trailing\\ ",    "trailing ",          false, true),
+            ("trailing   ",    "trailing",           false, true),
+        ];
+
+        for (pattern, relative, is_dir, expected) in cases {
+            assert_eq!(
+                ignores(pattern, relative, is_dir),
+                expected,
+                "{pattern:?} on {relative:?} (dir: {is_dir})"
+            );
+        }
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        for line in ["", "   ", "# comment", "!", "/", "!/"] {
+            assert!(Pattern::parse(line).is_none(), "{line:?} should be skipped");
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// src/listenbrainz.rs
+//! A minimal client for the `ListenBrainz` listen submission API
+//!
+//! Listens are sent as JSON with a user token. Self-hosted `ListenBrainz` instances and Maloja's
+//! `ListenBrainz`-compatible API work the same way at a different `api_url`. Requests block, so
+//! they should be made from `spawn_blocking`.
+
+use std::{
+    sync::LazyLock,
+    time::Duration,
+};
+
+use anyhow::{
+    Result,
+    bail,
+};
+use serde_json::{
+    Map,
+    Value,
+    json,
+};
+use tracing::debug;
+use ureq::Agent;
+
+use crate::scrobbler::{
+    Scrobble,
+    Scrobbler,
+};
+
+/// The most listens `ListenBrainz` accepts in one import
+pub const BATCH_SIZE: usize = 1000;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static AGENT: LazyLock<Agent> = LazyLock::new(|| {
+    Agent::config_builder()
+        // ListenBrainz explains its errors in the body, so don't throw it away
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into()
+});
+
+/// Builds a listen's payload, leaving out `listened_at` for now playing updates
+fn listen(scrobble: &Scrobble, listened_at: bool) -> Value {
+    let mut info = Map::new();
+    info.insert("submission_client".to_owned(), json!("tuun"));
+    info.insert(
+        "submission_client_version".to_owned(),
+        json!(env!("CARGO_PKG_VERSION")),
+    );
+    if let Some(album_artist) = &scrobble.album_artist {
+        info.insert("release_artist_name".to_owned(), json!(album_artist));
+    }
+    if let Some(track_number) = scrobble.track_number {
+        info.insert("tracknumber".to_owned(), json!(track_number));
+    }
+    if let Some(duration) = scrobble.duration {
+        info.insert("duration_ms".to_owned(), json!(duration * 1000));
+    }
+
+    let mut metadata = json!({
+        "artist_name": scrobble.artist,
+        "track_name": scrobble.title,
+        "additional_info": info,
+    });
+    if let Some(album) = scrobble.known_album() {
+        metadata["release_name"] = json!(album);
+    }
+
+    let mut listen = json!({ "track_metadata": metadata });
+    if listened_at {
+        listen["listened_at"] = json!(scrobble.timestamp);
+    }
+    listen
+}
+
+/// Builds a submission of several listens, as a `single` listen if there's only one and an
+/// `import` otherwise
+fn submission(scrobbles: &[Scrobble]) -> Value {
+    let listen_type = if scrobbles.len() == 1 { "single" } else { "import" };
+    let payload = scrobbles
+        .iter()
+        .map(|s| listen(s, true))
+        .collect::<Vec<_>>();
+    json!({
+        "listen_type": listen_type,
+        "payload": payload,
+    })
+}
+
+/// A scrobbler for `ListenBrainz` or anything that speaks its API
+pub struct ListenBrainz {
+    api_url: String,
+    token:   String,
+}
+
+impl ListenBrainz {
+    pub fn new(api_url: &str, token: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            token:   token.to_owned(),
+        }
+    }
+
+    fn submit_url(&self) -> String { format!("{}/1/submit-listens", self.api_url) }
+
+    fn send(&self, body: &Value) -> Result<()> {
+        let url = self.submit_url();
+        debug!(
+            "Submitting {} listen to {url}",
+            body["listen_type"].as_str().unwrap_or_default()
+        );
+        let mut response = AGENT
+            .post(&url)
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "application/json")
+            .send(body.to_string())?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.body_mut().read_to_string().unwrap_or_default();
+            let message = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|v| v.get("error")?.as_str().map(ToOwned::to_owned))
+                .unwrap_or(body);
+            bail!("{url} returned {status}: {message}");
+        }
+        Ok(())
+    }
+}
+
+impl Scrobbler for ListenBrainz {
+    fn batch_size(&self) -> usize { BATCH_SIZE }
+
+    fn connect(&self) -> Result<()> {
+        if self.token.is_empty() {
+            bail!("Cowardly refusing to submit listens without a token");
+        }
+        Ok(())
+    }
+
+    fn now_playing(&self, scrobble: &Scrobble) -> Result<()> {
+        self.send(&json!({
+            "listen_type": "playing_now",
+            "payload": [listen(scrobble, false)],
+        }))
+    }
+
+    fn scrobble(&self, scrobbles: &[Scrobble]) -> Result<()> {
+        if scrobbles.len() > BATCH_SIZE {
+            bail!("Can't submit more than {BATCH_SIZE} listens at once");
+        }
+        self.send(&submission(scrobbles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrobble(title: &str, timestamp: u64) -> Scrobble {
+        Scrobble {
+            artist: "Artist".to_owned(),
+            title: title.to_owned(),
+            album: "<Unknown album>".to_owned(),
+            album_artist: None,
+            track_number: Some(3),
+            duration: Some(200),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn lone_listens_are_single_and_batches_are_imports() {
+        let single = submission(&[scrobble("One", 100)]);
+        assert_eq!(single["listen_type"], "single");
+        assert_eq!(single["payload"][0]["listened_at"], 100);
+
+        let import = submission(&[scrobble("One", 100), scrobble("Two", 200)]);
+        assert_eq!(import["listen_type"], "import");
+        assert_eq!(import["payload"][1]["listened_at"], 200);
+        assert_eq!(import["payload"][1]["track_metadata"]["track_name"], "Two");
+    }
+
+    #[test]
+    fn listens_leave_out_unknowns() {
+        let now_playing = listen(&scrobble("One", 100), false);
+        assert!(now_playing.get("listened_at").is_none());
+
+        let metadata = &now_playing["track_metadata"];
+        assert!(metadata.get("release_name").is_none());
+        assert_eq!(metadata["additional_info"]["tracknumber"], 3);
+        assert_eq!(metadata["additional_info"]["duration_ms"], 200_000);
+    }
+
+    #[test]
+    fn api_url_is_joined_with_one_slash() {
+        for api_url in [
+            "https://api.listenbrainz.org",
+            "https://api.listenbrainz.org/",
+            "https://api.listenbrainz.org//",
+        ] {
+            assert_eq!(
+                ListenBrainz::new(api_url, "token").submit_url(),
+                "https://api.listenbrainz.org/1/submit-listens"
+            );
+        }
+
+        // Maloja serves the API under a path
+        assert_eq!(
+            ListenBrainz::new("https://maloja.example/apis/listenbrainz/", "token").submit_url(),
+            "https://maloja.example/apis/listenbrainz/1/submit-listens"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+// src/loved.rs
+//! Logic for loved tracks
+//!
+//! Loved tracks are kept in `$XDG_STATE_HOME/tuun/loved.json` and listed in `loved.tpl`, newest
+//! first. Loving or unloving a track also syncs it to the scrobbling services that keep loved
+//! tracks, which are Last.fm and Libre.fm.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        LazyLock,
+        Mutex,
+        PoisonError,
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+use tracing::{
+    debug,
+    error,
+    info,
+    warn,
+};
+
+use crate::{
+    config::get_state_dir,
+    ctl::exit_code,
+    ipc::{
+        self,
+        Event,
+        Request,
+    },
+    playlists,
+    scrobbler::{
+        self,
+        Scrobble,
+    },
+    structs::{
+        Track,
+        unix_now,
+    },
+};
+
+static LOVED: LazyLock<Mutex<Vec<LovedTrack>>> = LazyLock::new(|| Mutex::new(load()));
+
+/// A loved track
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LovedTrack {
+    path:     Option<PathBuf>,
+    artist:   String,
+    title:    String,
+    /// Unix timestamp of when the track was loved
+    loved_at: u64,
+}
+
+impl LovedTrack {
+    /// Whether this is the given track, going by path if both have one
+    fn is(&self, track: &Track) -> bool {
+        match (&self.path, &track.path) {
+            | (Some(a), Some(b)) => a == b,
+            | _ => self.artist == track.get_primary_artist() && self.title == track.title,
+        }
+    }
+}
+
+fn loved_file() -> Option<PathBuf> { get_state_dir().map(|d| d.join("tuun/loved.json")) }
+
+/// Reads the loved tracks from disk, treating a missing or unreadable file as empty
+fn load() -> Vec<LovedTrack> {
+    let Some(path) = loved_file() else {
+        warn!("Couldn't find a state directory for loved tracks");
+        return Vec::new();
+    };
+
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+
+    match serde_json::from_str::<Vec<LovedTrack>>(&contents) {
+        | Ok(loved) => {
+            debug!("Loaded {} loved tracks", loved.len());
+            loved
+        },
+        | Err(e) => {
+            error!("Failed to parse loved tracks at '{}': {e}", path.display());
+            Vec::new()
+        },
+    }
+}
+
+fn save(loved: &[LovedTrack]) -> Result<()> {
+    let path = loved_file().context("Couldn't find a state directory")?;
+    let dir = path.parent().context("Loved path should have a parent")?;
+    fs::create_dir_all(dir)?;
+
+    // Write to a temporary file first so an interrupted save can't lose loved tracks
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(loved)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Whether a track is loved
+pub fn is_loved(track: &Track) -> bool {
+    LOVED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .any(|l| l.is(track))
+}
+
+/// The paths of loved tracks, most recently loved first
+pub fn paths() -> Vec<PathBuf> {
+    LOVED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .rev()
+        .filter_map(|l| l.path.clone())
+        .collect()
+}
+
+/// Loves or unloves a track locally
+fn update(track: &Track, loved: bool) -> Result<()> {
+    let mut tracks = LOVED.lock().unwrap_or_else(PoisonError::into_inner);
+    if loved && !tracks.iter().any(|l| l.is(track)) {
+        tracks.push(LovedTrack {
+            path:     track.path.clone(),
+            artist:   track.get_primary_artist(),
+            title:    track.title.clone(),
+            loved_at: unix_now(),
+        });
+    } else if !loved {
+        tracks.retain(|l| !l.is(track));
+    }
+
+    let saved = save(&tracks);
+    drop(tracks);
+    saved
+}
+
+/// # Description
+/// Loves or unloves a track, then syncs it to scrobbling services.
+///
+/// The track is loved locally even if syncing fails.
+pub async fn set(track: &Track, loved: bool) -> Result<()> {
+    update(track, loved).context("Failed to save loved tracks")?;
+    if let Err(e) = playlists::write_loved_playlist() {
+        warn!("{e:#}");
+    }
+    info!("{} '{track}'", if loved { "Loved" } else { "Unloved" });
+    ipc::emit(Event::Loved { track: track.clone(), loved });
+
+    // Sync even if nothing changed locally, in case an earlier sync failed
+    scrobbler::love(&Scrobble::new(track, unix_now()), loved)
+        .await
+        .context("Saved locally, but couldn't sync")
+}
+
+/// # Description
+/// Runs `tuun love` or `tuun unlove` against the running instance, returning an exit code.
+pub async fn run(loved: bool) -> i32 { exit_code(love(loved).await, ipc::SOCK_PATH) }
+
+async fn love(loved: bool) -> Result<()> {
+    let request = if loved { Request::Love } else { Request::Unlove };
+    let track = ipc::request(&request).await?.unwrap_or_default();
+
+    let field = |f: &str| {
+        track
+            .get(f)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    };
+    println!(
+        "{} '{} - {}'",
+        if loved { "Loved" } else { "Unloved" },
+        field("artist"),
+        field("title")
+    );
+    Ok(())
+}
@@ -2,19 +2,18 @@ use std::{
     env,
     fs,
     io::ErrorKind as IOE,
-    path::PathBuf,
     process::exit,
-    sync::{
-        Arc,
-        LazyLock,
-    },
+    sync::LazyLock,
 };
 
-use config::Config;
+use args::Command;
+use config::{
+    Config,
+    Loaded,
+};
 use discord_rich_presence::DiscordIpcClient;
 use integrations::connect_discord_rpc_client;
 use permitit::Permit;
-use rustfm_scrobble::Scrobbler;
 use tokio::sync::Mutex;
 use tracing::{
     error,
@@ -28,32 +27,78 @@ use tracing_subscriber::{
 
 mod args;
 mod config;
+mod ctl;
+#[cfg(test)]
+mod fake_mpv;
+mod history;
+mod index;
 mod integrations;
+mod ipc;
+mod lastfm;
+mod library;
+mod listenbrainz;
+mod loved;
+mod mpris;
 mod mpv;
 mod playlists;
+mod queue;
+mod report;
+mod scrobbler;
+mod session;
+mod spool;
+mod stats;
+mod status;
 mod structs;
+mod tags;
+mod watcher;
 
-pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
+pub static CONFIG: Loaded = Loaded::new();
 pub static ARGS: LazyLock<args::Args> = LazyLock::new(args::parse_args);
 pub static RPC_CLIENT: LazyLock<Mutex<DiscordIpcClient>> =
     LazyLock::new(|| Mutex::new(DiscordIpcClient::new(&CONFIG.discord.client_id)));
-pub static SCROBBLER: LazyLock<Mutex<Option<Arc<Scrobbler>>>> = LazyLock::new(|| Mutex::new(None));
 
 /// # Description
 /// Main loop (should never return)
 ///
-/// Does stuff in this order:
+/// Subcommands are dispatched first and exit without starting anything.
+///
+/// Otherwise, does stuff in this order:
 ///     1. Initialize logging
-///     2. Create `/tmp/tuun`
-///     3. Create `/tmp/tuun/tuun.lock`
-///     4. Establish the music directory
-///     5. Generate playlists
-///     6. Optionally connect to Discord
-///     7. Optionally authenticate with `LastFM`
-///     8. Launch MPV
-///     9. Block forever
+///     2. Load the config and resolve its secrets
+///     3. Create `/tmp/tuun`
+///     4. Create `/tmp/tuun/tuun.lock`
+///     5. Serve `/tmp/tuun/tuunsocket`
+///     6. Check the library roots
+///     7. Refresh the library index and generate playlists
+///     8. Optionally watch the library for changes
+///     9. Optionally connect to Discord
+///    10. Optionally connect scrobblers and retry spooled scrobbles
+///    11. Optionally serve MPRIS
+///    12. Launch MPV
+///    13. Block forever
 #[tokio::main]
 async fn main() -> ! {
+    // Subcommands talk to an already running instance, so handle them before touching any of
+    // that instance's state
+    match &ARGS.command {
+        | Some(Command::Ctl { action }) => exit(ctl::run(action).await),
+        | Some(Command::Queue { action }) => exit(queue::run(action).await),
+        | Some(Command::Status { format }) => exit(status::run(format.as_deref()).await),
+        | Some(Command::Stats { range, by, limit, json }) => {
+            exit(stats::run(*range, *by, *limit, *json))
+        },
+        | Some(Command::Report { period, format, output, limit }) => {
+            exit(report::run(*period, *format, output.as_ref(), *limit))
+        },
+        | Some(Command::Lastfm { action }) => {
+            load_with_secrets();
+            exit(lastfm::run(action))
+        },
+        | Some(Command::Love) => exit(loved::run(true).await),
+        | Some(Command::Unlove) => exit(loved::run(false).await),
+        | None => {},
+    }
+
     // Initialize logging
     let _ = fs::write("/tmp/tuun/log", "");
     let file_appender = rolling::never("/tmp/tuun", "log");
@@ -76,6 +121,9 @@ async fn main() -> ! {
 
     info!("Starting tuun");
 
+    // Load the config before anything else runs, since resolving secrets may wait on commands
+    load_with_secrets();
+
     // Create /tmp/tuun
     if let Err(e) = fs::create_dir("/tmp/tuun").permit(|e| e.kind() == IOE::AlreadyExists) {
         error!("Failed to create /tmp/tuun: {e}");
@@ -90,28 +138,55 @@ async fn main() -> ! {
 
     info!("Created lock");
 
-    let music_dir = &CONFIG.general.music_dir;
-    if !PathBuf::from(music_dir).exists() {
-        error!("Music directory '{music_dir}' does not exist!");
+    // Serve tuun's control socket
+    tokio::spawn(async {
+        if let Err(e) = ipc::serve().await {
+            error!("Control socket failed: {e:#}");
+        }
+    });
+
+    // Make sure the library roots exist
+    if let Err(e) = library::check_roots() {
+        error!("{e}");
         exit(1)
     }
 
-    // Create auto-generated playlists
-    playlists::create_all_playlist();
-    playlists::create_recent_playlist();
+    // Refresh the library index and create auto-generated playlists from it
+    let index = library::index();
+    if let Err(e) = playlists::create_all_playlist(&index)
+        .and_then(|()| playlists::create_recent_playlist(&index))
+        .and_then(|()| playlists::create_loved_playlist())
+    {
+        error!("{e:#}");
+        exit(1)
+    }
+
+    // Keep the index and generated playlists current while running
+    if CONFIG.general.watch_library {
+        tokio::spawn(async {
+            if let Err(e) = watcher::watch(index).await {
+                error!("Failed to watch the library: {e:#}");
+            }
+        });
+    }
 
     // Connect to discord if it's used
     if CONFIG.discord.used {
         connect_discord_rpc_client().await;
     }
 
-    // Authenticate LastFM scrobbler in the background if it's used
-    if CONFIG.lastfm.used {
+    // Authenticate scrobblers in the background and retry scrobbles that failed, including those
+    // from previous runs
+    if scrobbler::any_used() {
+        scrobbler::connect();
+        tokio::spawn(spool::retry());
+    }
+
+    // Expose tuun over MPRIS if it's used
+    if CONFIG.mpris.used {
         tokio::spawn(async {
-            if let Err(e) = integrations::authenticate_lastfm_scrobbler().await {
-                error!("Error during scrobbler authentication: {e:#?}");
-            } else {
-                info!("Authenticated with lastfm");
+            if let Err(e) = mpris::serve().await {
+                error!("Failed to serve MPRIS: {e:#}");
             }
         });
     }
@@ -120,6 +195,11 @@ async fn main() -> ! {
     tokio::spawn(async {
         info!("Launching MPV");
         mpv::launch().await;
+        tokio::spawn(async {
+            if let Err(e) = queue::watch().await {
+                error!("Failed to watch for queued tracks: {e:#}");
+            }
+        });
         if let Err(e) = mpv::connect().await {
             error!("Failed to connect to MPV's socket: {e:#?}");
         }
@@ -130,3 +210,14 @@ async fn main() -> ! {
         std::thread::park();
     }
 }
+
+/// # Description
+/// Loads the config and resolves its secrets.
+///
+/// Secret commands can take a while (waiting on a passphrase, for instance), so this should be
+/// called before anything else reads the config or any tasks are spawned.
+fn load_with_secrets() {
+    let mut config = Config::load();
+    config.resolve_secrets();
+    CONFIG.set(config);
+}
@@ -0,0 +1,411 @@
+// src/mpris.rs
+//! Logic for exposing tuun over MPRIS2
+//!
+//! This lets playerctl, desktop media widgets, and media keys see and control tuun.
+//! <https://specifications.freedesktop.org/mpris-spec/latest/>
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+};
+
+use anyhow::Result;
+use serde_json::{
+    Value,
+    json,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{
+    debug,
+    info,
+    instrument,
+    warn,
+};
+use zbus::{
+    fdo,
+    interface,
+    object_server::{
+        InterfaceRef,
+        SignalEmitter,
+    },
+    zvariant::{
+        ObjectPath,
+        OwnedValue,
+        Value as Variant,
+    },
+};
+
+use crate::{
+    ipc::{
+        self,
+        Event,
+    },
+    mpv::{
+        self,
+        LOOPED,
+        MUTED,
+        PAUSED,
+        SHUFFLED,
+        TRACK,
+        VOLUME,
+    },
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.tuun";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// Bumped whenever the track changes so clients get a fresh `mpris:trackid`
+static TRACK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// # Description
+/// Registers tuun on the session bus and forwards player events as `PropertiesChanged` signals
+#[instrument]
+pub async fn serve() -> Result<()> {
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(OBJECT_PATH, Player)?
+        .build()
+        .await?;
+    info!("Registered {BUS_NAME}");
+
+    let player = connection
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)
+        .await?;
+
+    let mut events = ipc::subscribe();
+    loop {
+        match events.recv().await {
+            | Ok(event) => {
+                if let Err(e) = notify(&player, &event).await {
+                    warn!("Failed to emit MPRIS signal for {event:?}: {e}");
+                }
+            },
+            | Err(RecvError::Lagged(n)) => warn!("MPRIS lagged behind by {n} events"),
+            | Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn notify(player: &InterfaceRef<Player>, event: &Event) -> zbus::Result<()> {
+    let emitter = player.signal_emitter();
+
+    match event {
+        | Event::Track { .. } => {
+            TRACK_ID.fetch_add(1, Ordering::Relaxed);
+            player.get().await.metadata_changed(emitter).await?;
+            player.get().await.playback_status_changed(emitter).await
+        },
+        | Event::Pause { .. } => player.get().await.playback_status_changed(emitter).await,
+        | Event::Loop { .. } => player.get().await.loop_status_changed(emitter).await,
+        | Event::Shuffle { .. } => player.get().await.shuffle_changed(emitter).await,
+        | Event::Volume { .. } | Event::Mute { .. } => {
+            player.get().await.volume_changed(emitter).await
+        },
+        | Event::Seeked { position } => Player::seeked(emitter, micros(*position)).await,
+        | _ => Ok(()),
+    }
+}
+
+/// Sends a command to mpv, translating failures into D-Bus errors
+async fn mpv(command: Value) -> fdo::Result<()> {
+    debug!("Sending mpv command for MPRIS: {command}");
+    mpv::command(command)
+        .await
+        .map(|_| ())
+        .map_err(|e| fdo::Error::Failed(format!("{e:#}")))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn micros(secs: f64) -> i64 { (secs * 1_000_000.) as i64 }
+
+fn track_id() -> ObjectPath<'static> {
+    let id = TRACK_ID.load(Ordering::Relaxed);
+    ObjectPath::try_from(format!("/org/tuun/track/{id}"))
+        .expect("Track ids should be valid object paths")
+}
+
+fn owned(value: Variant<'_>) -> OwnedValue {
+    OwnedValue::try_from(value).expect("Metadata values shouldn't contain file descriptors")
+}
+
+/// The `org.mpris.MediaPlayer2` interface
+struct Root;
+
+// Interface members have to be methods, and are async for consistency with the rest
+#[allow(clippy::unused_async, clippy::unused_self)]
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    async fn raise(&self) {}
+
+    async fn quit(&self) -> fdo::Result<()> { mpv(json!(["quit"])).await }
+
+    #[zbus(property)]
+    async fn can_quit(&self) -> bool { true }
+
+    #[zbus(property)]
+    async fn can_raise(&self) -> bool { false }
+
+    #[zbus(property)]
+    async fn has_track_list(&self) -> bool { false }
+
+    #[zbus(property)]
+    async fn identity(&self) -> &str { "tuun" }
+
+    #[zbus(property)]
+    async fn desktop_entry(&self) -> &str { "tuun" }
+
+    #[zbus(property)]
+    async fn supported_uri_schemes(&self) -> Vec<String> { vec!["file".to_owned()] }
+
+    #[zbus(property)]
+    async fn supported_mime_types(&self) -> Vec<String> {
+        [
+            "audio/mpeg",
+            "audio/flac",
+            "audio/ogg",
+            "audio/opus",
+            "audio/mp4",
+            "audio/wav",
+        ]
+        .map(ToOwned::to_owned)
+        .to_vec()
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface
+struct Player;
+
+#[allow(clippy::unused_async, clippy::unused_self)]
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn next(&self) -> fdo::Result<()> { mpv(json!(["playlist-next"])).await }
+
+    async fn previous(&self) -> fdo::Result<()> { mpv(json!(["playlist-prev"])).await }
+
+    async fn pause(&self) -> fdo::Result<()> { mpv(json!(["set", "pause", "yes"])).await }
+
+    async fn play_pause(&self) -> fdo::Result<()> { mpv(json!(["cycle", "pause"])).await }
+
+    // tuun doesn't really have a stopped state, so this just pauses
+    async fn stop(&self) -> fdo::Result<()> { mpv(json!(["set", "pause", "yes"])).await }
+
+    async fn play(&self) -> fdo::Result<()> { mpv(json!(["set", "pause", "no"])).await }
+
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        #[allow(clippy::cast_precision_loss)]
+        let offset = offset as f64 / 1_000_000.;
+        mpv(json!(["seek", offset, "relative", "exact"])).await
+    }
+
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        // Stale track ids should be ignored per the spec
+        if track_id != self::track_id() {
+            return Ok(());
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let position = position as f64 / 1_000_000.;
+        mpv(json!(["seek", position, "absolute", "exact"])).await
+    }
+
+    async fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let path = uri.strip_prefix("file://").unwrap_or(uri);
+        let path = urlencoding::decode(path).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        mpv(json!(["loadfile", path, "replace"])).await
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    async fn playback_status(&self) -> &str {
+        if TRACK.lock().await.is_default() {
+            "Stopped"
+        } else if PAUSED.load(Ordering::Relaxed) {
+            "Paused"
+        } else {
+            "Playing"
+        }
+    }
+
+    /// tuun always loops the playlist, so the loop status is either `Track` or `Playlist`
+    #[zbus(property)]
+    async fn loop_status(&self) -> &str {
+        if LOOPED.load(Ordering::Relaxed) { "Track" } else { "Playlist" }
+    }
+
+    #[zbus(property)]
+    async fn set_loop_status(&self, status: String) -> fdo::Result<()> {
+        let loop_file = if status == "Track" { "inf" } else { "no" };
+        mpv(json!(["set", "loop-file", loop_file])).await
+    }
+
+    #[zbus(property)]
+    async fn rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    async fn minimum_rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    async fn maximum_rate(&self) -> f64 { 1.0 }
+
+    #[zbus(property)]
+    async fn shuffle(&self) -> bool { SHUFFLED.load(Ordering::Relaxed) }
+
+    #[zbus(property)]
+    async fn set_shuffle(&self, shuffle: bool) -> fdo::Result<()> {
+        if shuffle {
+            mpv(json!(["playlist-shuffle"])).await?;
+        } else {
+            mpv(json!(["playlist-unshuffle"])).await?;
+        }
+        mpv(json!(["set_property", "shuffle", shuffle])).await
+    }
+
+    #[zbus(property)]
+    async fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let track = TRACK.lock().await.clone();
+        let mut metadata = HashMap::new();
+
+        metadata.insert("mpris:trackid".to_owned(), owned(track_id().into()));
+        metadata.insert(
+            "mpris:length".to_owned(),
+            owned(micros(track.duration).into()),
+        );
+        metadata.insert("xesam:title".to_owned(), owned(track.title.as_str().into()));
+        metadata.insert("xesam:album".to_owned(), owned(track.album.as_str().into()));
+
+        let artists = track
+            .artist
+            .split(", ")
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        metadata.insert("xesam:artist".to_owned(), owned(artists.into()));
+
+        if !track.arturl.is_empty() {
+            metadata.insert(
+                "mpris:artUrl".to_owned(),
+                owned(track.arturl.as_str().into()),
+            );
+        }
+
+        if let Some(path) = &track.path {
+            let url = format!("file://{}", urlencoding::encode(&path.to_string_lossy()))
+                .replace("%2F", "/");
+            metadata.insert("xesam:url".to_owned(), owned(url.into()));
+        }
+
+        metadata
+    }
+
+    /// MPRIS volume is 0.0 to 1.0, while mpv's is a percentage
+    #[zbus(property)]
+    async fn volume(&self) -> f64 {
+        if MUTED.load(Ordering::Relaxed) {
+            return 0.0;
+        }
+        f64::from(VOLUME.load(Ordering::Relaxed)) / 100.
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&self, volume: f64) -> fdo::Result<()> {
+        mpv(json!([
+            "set_property",
+            "volume",
+            (volume.max(0.) * 100.).round()
+        ]))
+        .await
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn position(&self) -> i64 { micros(TRACK.lock().await.progress) }
+
+    #[zbus(property)]
+    async fn can_go_next(&self) -> bool { true }
+
+    #[zbus(property)]
+    async fn can_go_previous(&self) -> bool { true }
+
+    #[zbus(property)]
+    async fn can_play(&self) -> bool { true }
+
+    #[zbus(property)]
+    async fn can_pause(&self) -> bool { true }
+
+    #[zbus(property)]
+    async fn can_seek(&self) -> bool { true }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    async fn can_control(&self) -> bool { true }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use super::*;
+    use crate::fake_mpv::{
+        self,
+        FakeMpv,
+    };
+
+    #[test]
+    fn positions_are_in_microseconds() {
+        assert_eq!(micros(0.), 0);
+        assert_eq!(micros(37.5), 37_500_000);
+    }
+
+    #[tokio::test]
+    async fn seeks_are_sent_to_mpv_in_seconds() {
+        let fake = Arc::new(Mutex::new(FakeMpv::default()));
+        let _served = fake_mpv::serve(&fake);
+
+        let player = Player;
+        player.seek(-5_000_000).await.expect("Failed to seek");
+        player
+            .set_position(track_id(), 30_000_000)
+            .await
+            .expect("Failed to set position");
+
+        // Seeks meant for a track that's no longer playing are ignored
+        let stale = ObjectPath::try_from("/org/tuun/track/stale").expect("Valid object path");
+        player
+            .set_position(stale, 60_000_000)
+            .await
+            .expect("Failed to ignore stale seek");
+
+        assert_eq!(fake.lock().expect("Fake mpv lock poisoned").commands, [
+            json!(["seek", -5.0, "relative", "exact"]),
+            json!(["seek", 30.0, "absolute", "exact"]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn mpv_errors_fail_the_call() {
+        let fake = Arc::new(Mutex::new(FakeMpv::default().fail("seek")));
+        let _served = fake_mpv::serve(&fake);
+
+        let player = Player;
+        assert!(player.seek(-5_000_000).await.is_err());
+        player.set_volume(0.5).await.expect("Failed to set volume");
+        player
+            .set_shuffle(true)
+            .await
+            .expect("Failed to set shuffle");
+
+        assert_eq!(fake.lock().expect("Fake mpv lock poisoned").commands, [
+            json!(["seek", -5.0, "relative", "exact"]),
+            json!(["set_property", "volume", 50.0]),
+            json!(["playlist-shuffle"]),
+            json!(["set_property", "shuffle", true]),
+        ]);
+    }
+}
@@ -1,6 +1,9 @@
 use std::{
     fs,
-    path::PathBuf,
+    path::{
+        Path,
+        PathBuf,
+    },
     process::exit,
     sync::{
         Arc,
@@ -13,8 +16,14 @@ use std::{
     },
 };
 
-use anyhow::Result;
-use serde_json::Value;
+use anyhow::{
+    Result,
+    bail,
+};
+use serde_json::{
+    Value,
+    json,
+};
 use tokio::{
     io::{
         AsyncBufReadExt,
@@ -41,26 +50,41 @@ use tracing::{
 use crate::{
     ARGS,
     CONFIG,
-    integrations::{
-        lastfm_now_playing,
-        lastfm_scrobble,
+    history::{
+        self,
+        Ending,
+    },
+    ipc::{
+        self,
+        Event,
+    },
+    queue::{
+        self,
+        QUEUE_FILE,
+    },
+    scrobbler::{
+        self,
+        Scrobble,
     },
+    session,
     structs::Track,
 };
 
-const SOCK_PATH: &str = "/tmp/tuun/mpvsocket";
+pub const SOCK_PATH: &str = "/tmp/tuun/mpvsocket";
 
 pub static LOOPED: AtomicBool = AtomicBool::new(false);
 pub static PAUSED: AtomicBool = AtomicBool::new(false);
 pub static MUTED: AtomicBool = AtomicBool::new(false);
+pub static SHUFFLED: AtomicBool = AtomicBool::new(false);
 pub static VOLUME: AtomicU32 = AtomicU32::new(0);
+pub static NOW_PLAYING_SET: AtomicBool = AtomicBool::new(false);
+pub static SCROBBLED: AtomicBool = AtomicBool::new(false);
 
-static FRESH: AtomicBool = AtomicBool::new(false);
-static NOW_PLAYING_SET: AtomicBool = AtomicBool::new(false);
+/// Whether mpv is seeking, since it also restarts playback whenever it loads a file
+static SEEKING: AtomicBool = AtomicBool::new(false);
 
-static TRACK: LazyLock<Arc<Mutex<Track>>> =
+pub static TRACK: LazyLock<Arc<Mutex<Track>>> =
     LazyLock::new(|| Arc::new(Mutex::new(Track::default())));
-static QUEUE: LazyLock<PathBuf> = LazyLock::new(|| PathBuf::from("/tmp/tuun/quu.tpl"));
 
 pub async fn connect() -> Result<()> {
     // Connect to mpv's socket
@@ -78,6 +102,7 @@ pub async fn connect() -> Result<()> {
         r#"{"command": ["observe_property", 5, "playback-time"]}"#,
         r#"{"command": ["observe_property", 6, "metadata"]}"#,
         r#"{"command": ["observe_property", 7, "volume"]}"#,
+        r#"{"command": ["observe_property", 8, "shuffle"]}"#,
     ];
 
     // Send all subscription commands
@@ -112,36 +137,93 @@ pub async fn connect() -> Result<()> {
 
 #[instrument(level = "debug")]
 pub async fn send_command(command: &str) -> Result<Value> {
-    let stream = UnixStream::connect(SOCK_PATH).await?;
+    #[cfg(test)]
+    if let Some(path) = crate::fake_mpv::sock_path() {
+        return send_command_to(&path, command).await;
+    }
+    send_command_to(Path::new(SOCK_PATH), command).await
+}
+
+/// Sends a raw command to the mpv listening on a socket and returns its reply
+async fn send_command_to(sock_path: &Path, command: &str) -> Result<Value> {
+    let stream = UnixStream::connect(sock_path).await?;
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
-    debug!("Connected to mpv socket {SOCK_PATH:?}");
+    debug!("Connected to mpv socket {sock_path:?}");
 
     writer.write_all(command.as_bytes()).await?;
     writer.write_all(b"\n").await?;
     writer.flush().await?;
     debug!("Sent mpv command: {command:#}");
 
+    // mpv broadcasts events to every client, so skip anything that isn't a reply
     let mut response = String::with_capacity(64);
-    reader.read_line(&mut response).await?;
+    let json = loop {
+        response.clear();
+        if reader.read_line(&mut response).await? == 0 {
+            bail!("mpv closed the socket without responding");
+        }
 
-    let json: Value = serde_json::from_str(&response)?;
+        let json: Value = serde_json::from_str(&response)?;
+        if json.get("event").is_none() {
+            break json;
+        }
+    };
     debug!("Received mpv response: {json:#}");
 
     Ok(json)
 }
 
+/// Sends a command to mpv and checks that it succeeded
+pub async fn command(args: Value) -> Result<Value> {
+    let response = send_command(&json!({ "command": args }).to_string()).await?;
+
+    match response.get("error").and_then(Value::as_str) {
+        | Some("success") | None => Ok(response),
+        | Some(e) => bail!("mpv rejected {args}: {e}"),
+    }
+}
+
 /// # Description
 /// Handles MPV events.
-/// Supported events include start-file, end-file, and property-change.
+/// Supported events include start-file, end-file, seek, playback-restart, and property-change.
 async fn handle_events(json: Value) {
     if let Some(event) = json.get("event").and_then(|v| v.as_str()) {
         match event {
             | "start-file" => {
                 debug!("MPV Event: New file started");
+                SEEKING.store(false, Ordering::Relaxed);
+                session::begin().await;
+            },
+            | "seek" => {
+                debug!("MPV Event: Seeking");
+                SEEKING.store(true, Ordering::Relaxed);
+            },
+            | "playback-restart" => {
+                debug!("MPV Event: Playback restarted");
+                // Only a restart that finishes a seek means the position jumped
+                if !SEEKING.swap(false, Ordering::Relaxed) {
+                    return;
+                }
+
+                let position = send_command(r#"{"command": ["get_property", "playback-time"]}"#)
+                    .await
+                    .ok()
+                    .and_then(|r| r.get("data").and_then(Value::as_f64));
+                if let Some(position) = position {
+                    ipc::emit(Event::Seeked { position });
+                }
             },
             | "end-file" => {
                 if let Some(reason) = json.get("reason").and_then(|v| v.as_str()) {
+                    let ending = match reason {
+                        | "eof" => Ending::Natural,
+                        | "quit" => Ending::Quit,
+                        | _ => Ending::Skipped,
+                    };
+                    let track = TRACK.lock().await.clone();
+                    history::finish(ending, &track).await;
+
                     if reason == "quit" {
                         info!("MPV quit. Exiting...");
                         exit(0)
@@ -162,13 +244,9 @@ async fn handle_events(json: Value) {
 }
 
 /// Handles MPV properties.
-/// Supported properties include filename, pause, loop-file, mute, and playback-time
+/// Supported properties include filename, pause, loop-file, mute, shuffle, and playback-time
 #[instrument(level = "trace")]
 async fn handle_properties(json: Value) {
-    if let Err(e) = queue().await {
-        error!("Failed to refresh queue: {e:#}");
-    }
-
     if let Some(property) = json.get("name").and_then(Value::as_str) {
         match property {
             | "filename" => {
@@ -179,6 +257,8 @@ async fn handle_properties(json: Value) {
                 debug!("Pause property: {json:#}");
                 if let Some(paused) = json.get("data").and_then(Value::as_bool) {
                     PAUSED.store(paused, Ordering::Relaxed);
+                    session::pause(paused).await;
+                    ipc::emit(Event::Pause { paused });
                     if paused {
                         info!("Paused");
                     } else {
@@ -194,6 +274,7 @@ async fn handle_properties(json: Value) {
                 if let Err(e) = track.update_metadata(&json).await {
                     error!("Failed to update metadata: {e:#?}");
                 }
+                ipc::emit(Event::Track { track: track.clone() });
 
                 drop(track);
             },
@@ -206,6 +287,7 @@ async fn handle_properties(json: Value) {
                         | _ => false,
                     };
                     LOOPED.store(looped, Ordering::Relaxed);
+                    ipc::emit(Event::Loop { looped });
                     if looped {
                         info!("Looped");
                     } else {
@@ -220,13 +302,27 @@ async fn handle_properties(json: Value) {
                     #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
                     let vol = vol.trunc() as u32;
                     VOLUME.store(vol, Ordering::Relaxed);
+                    ipc::emit(Event::Volume { volume: vol });
                     info!("Volume set to {vol}");
                 }
             },
+            | "shuffle" => {
+                debug!("Shuffle property: {json:#}");
+                if let Some(shuffled) = json.get("data").and_then(Value::as_bool) {
+                    SHUFFLED.store(shuffled, Ordering::Relaxed);
+                    ipc::emit(Event::Shuffle { shuffled });
+                    if shuffled {
+                        info!("Shuffled");
+                    } else {
+                        info!("Unshuffled");
+                    }
+                }
+            },
             | "mute" => {
                 debug!("Mute property: {json:#}");
                 if let Some(muted) = json.get("data").and_then(Value::as_bool) {
                     MUTED.store(muted, Ordering::Relaxed);
+                    ipc::emit(Event::Mute { muted });
                     if muted {
                         info!("Muted");
                     } else {
@@ -236,68 +332,61 @@ async fn handle_properties(json: Value) {
             },
             | "playback-time" => {
                 trace!("MPV Property: Playback time changed");
-                let mut track = TRACK.lock().await;
-                let time = json.get("data").and_then(Value::as_f64).unwrap_or(0.);
+                // mpv reports no playback time between files
+                let Some(time) = json.get("data").and_then(Value::as_f64) else {
+                    return;
+                };
                 trace!("Time: {time}");
+                let mut track = TRACK.lock().await;
 
-                // If a track is fresh, it can be scrobbled and its now playing status has not yet
-                // been set.
-                if time == 0.0 {
-                    debug!("Track is fresh");
-                    FRESH.store(true, Ordering::Relaxed);
-                    NOW_PLAYING_SET.store(false, Ordering::Relaxed);
+                // The track looped without mpv ending the file
+                if session::has_looped(time, track.duration).await {
+                    debug!("Track looped");
+                    history::finish(Ending::Natural, &track).await;
+                    session::begin().await;
                 }
 
+                session::progress(time).await;
                 track.update_progress(time);
                 track.display();
 
+                let Some(play) = session::current().await else {
+                    return;
+                };
+
                 // Set now playing status if the track has been playing for more than a
                 // configureable delay, or it's more than 5% through.
                 #[allow(clippy::cast_precision_loss)]
                 let delay =
                     (track.duration * 0.05).min(CONFIG.general.now_playing_delay as f64 / 1000.);
 
-                if time >= delay && !NOW_PLAYING_SET.load(Ordering::Relaxed) {
-                    NOW_PLAYING_SET.store(true, Ordering::Relaxed);
+                if play.listened >= delay && session::mark_now_playing().await {
                     info!("Now playing '{track}'");
+                    ipc::emit(Event::NowPlaying { track: track.clone() });
                     debug!("Pushing now playing status");
 
-                    if CONFIG.lastfm.used {
-                        info!("Setting LastFM now playing");
-                        let track_copy = track.clone();
-                        // TODO: Consider making `lastfm_now_playing` spawn its own thread rather
-                        // than having the caller do it
-                        tokio::spawn(async move {
-                            if let Err(e) = lastfm_now_playing(track_copy).await {
-                                error!("Failed to set LastFM now playing: {e:#?}");
-                            }
-                        });
+                    if scrobbler::any_used() {
+                        info!("Setting now playing for scrobblers");
+                        scrobbler::now_playing(&Scrobble::new(&track, play.started));
                     }
 
                     if CONFIG.discord.used {
                         info!("Setting Discord Rich Presence");
-                        track.rpc(Duration::from_secs_f64(delay)).await;
+                        track.rpc(Duration::from_secs_f64(play.position)).await;
                     }
                 }
 
-                // Scrobble track if it's more than a configurable percent through.
-                if time >= (track.duration * (f64::from(CONFIG.lastfm.scrobble_percent) / 100.))
-                    && FRESH.load(Ordering::Relaxed)
+                // Scrobble track once it's been listened to for long enough
+                if scrobbler::is_eligible(track.duration, play.listened)
+                    && session::mark_scrobbled().await
+                    && scrobbler::any_used()
                 {
-                    FRESH.store(false, Ordering::Relaxed);
-
-                    if CONFIG.lastfm.used {
-                        // TODO: Implement display for track so the logs look nicer
-                        info!("Scrobbling track: {track:#?}");
-                        let track_copy = track.clone();
-                        drop(track);
-                        tokio::spawn(async move {
-                            if let Err(e) = lastfm_scrobble(track_copy).await {
-                                error!("Failed to scrobble track: {e:#?}");
-                            }
-                        });
-                    }
+                    // TODO: Implement display for track so the logs look nicer
+                    info!("Scrobbling track: {track:#?}");
+                    let scrobble = Scrobble::new(&track, play.started);
+                    scrobbler::scrobble(&scrobble, &track);
                 }
+                drop(track);
             },
             | _ => {
                 warn!("MPV Property: Received unrecognized property:\n{json:#}");
@@ -351,7 +440,7 @@ pub async fn launch() {
         }
     }
 
-    match queue().await {
+    match queue::ingest().await {
         | Ok(queued) => {
             if queued {
                 info!("Starting with queued tracks");
@@ -364,13 +453,16 @@ pub async fn launch() {
     }
 }
 
+/// Returns the playlist tuun was started with
+pub fn starting_playlist() -> String {
+    ARGS.playlist
+        .clone()
+        .unwrap_or_else(|| CONFIG.general.playlist.clone())
+}
+
 #[instrument]
 fn prequeue() -> Vec<String> {
-    // FIXME: This can probably be written less grossly(?)
-    let playlist = &ARGS
-        .playlist
-        .clone()
-        .unwrap_or_else(|| CONFIG.general.playlist.clone());
+    let playlist = &starting_playlist();
 
     debug!("Starting with playlist '{playlist}'");
     if !PathBuf::from(playlist).exists() {
@@ -378,10 +470,10 @@ fn prequeue() -> Vec<String> {
         panic!("Playlist '{playlist}' does not exist");
     }
 
-    let args = if QUEUE.exists() {
+    let args = if QUEUE_FILE.exists() {
         debug!("Queue.tpl exists");
         vec![
-            format!("--playlist={}", QUEUE.display()),
+            format!("--playlist={}", QUEUE_FILE.display()),
             format!("--playlist={playlist}"),
         ]
     } else {
@@ -391,26 +483,3 @@ fn prequeue() -> Vec<String> {
     debug!("Prequeue args for mpv: {args:#?}");
     args
 }
-
-#[instrument]
-async fn queue() -> Result<bool> {
-    let queue = &*QUEUE;
-
-    trace!("Checking whether queue {queue:?} exists...");
-    if !queue.exists() {
-        trace!("No songs queued");
-        return Ok(false);
-    }
-
-    let songs = fs::read_to_string(queue)?;
-    for song in songs.lines() {
-        let song = song.trim();
-        let command = format!(r#"{{ "command": ["loadfile", "{song}", "insert-next"] }}"#);
-        send_command(&command).await?;
-        info!("Queued {song}");
-    }
-
-    fs::remove_file(queue)?;
-    debug!("Removed queue file {queue:?}");
-    Ok(true)
-}
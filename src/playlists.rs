@@ -6,6 +6,10 @@ use std::{
     path::PathBuf,
 };
 
+use anyhow::{
+    Context,
+    Result,
+};
 use tracing::{
     debug,
     info,
@@ -14,7 +18,12 @@ use tracing::{
     warn,
 };
 
-use crate::CONFIG;
+use crate::{
+    CONFIG,
+    index::Index,
+    library,
+    loved,
+};
 
 #[derive(Debug)]
 pub struct Playlist {
@@ -30,7 +39,7 @@ impl Playlist {
     }
 
     #[instrument(level = "trace")]
-    pub fn write(&self, songs: &[PathBuf]) {
+    pub fn write(&self, songs: &[PathBuf]) -> Result<()> {
         let contents = songs
             .iter()
             .filter(|p| p.is_file())
@@ -38,59 +47,78 @@ impl Playlist {
             .collect::<Vec<_>>()
             .join("\n");
 
-        fs::write(&self.playlist_path, &contents).expect("Failed to write playlist");
+        fs::write(&self.playlist_path, &contents).with_context(|| {
+            format!(
+                "Failed to write playlist '{}'",
+                self.playlist_path.display()
+            )
+        })?;
         debug!("Wrote playlist: {self:#?}");
         trace!("Playlist contents: {contents}");
+        Ok(())
     }
 }
 
-#[instrument]
-pub fn create_all_playlist() {
-    let path = PathBuf::from("/tmp/tuun/all.tpl");
-
-    // only recreate all.tpl on restarts since it resides in /tmp
-    if path.exists() {
-        return;
-    }
+pub const ALL_PLAYLIST: &str = "/tmp/tuun/all.tpl";
+pub const RECENT_PLAYLIST: &str = "/tmp/tuun/recent.tpl";
+pub const LOVED_PLAYLIST: &str = "/tmp/tuun/loved.tpl";
 
+#[instrument(skip(index))]
+pub fn create_all_playlist(index: &Index) -> Result<()> {
+    // always recreate all.tpl on startup so tracks added while tuun wasn't running show up
+    // while running, the library watcher keeps it current
     debug!("Creating the all playlist...");
-    let all_playlist = Playlist::new(path);
-
-    let songs = fs::read_dir(&CONFIG.general.music_dir)
-        .expect("Failed to read music directory")
-        .map_while(Result::ok)
-        .map(|e| e.path())
-        .filter(|p| p.is_file())
-        .collect::<Vec<_>>();
+    write_all_playlist(index)?;
+    info!("Created the all playlist");
+    Ok(())
+}
 
-    all_playlist.write(&songs);
-    info!("Created the all playlist")
+#[instrument(skip(index))]
+pub fn create_recent_playlist(index: &Index) -> Result<()> {
+    debug!("Creating the recent playlist...");
+    write_recent_playlist(index)?;
+    info!("Created the recent playlist");
+    Ok(())
 }
 
 #[instrument]
-pub fn create_recent_playlist() {
-    let path = PathBuf::from("/tmp/tuun/recent.tpl");
+pub fn create_loved_playlist() -> Result<()> {
+    debug!("Creating the loved playlist...");
+    write_loved_playlist()?;
+    info!("Created the loved playlist");
+    Ok(())
+}
 
-    if path.exists() {
-        return;
-    }
+/// Writes every track under a library root that's included in `all.tpl`
+pub fn write_all_playlist(index: &Index) -> Result<()> {
+    let all_playlist = Playlist::new(PathBuf::from(ALL_PLAYLIST));
 
-    debug!("Creating the recent playlist...");
-    let recent_playlist = Playlist::new(path);
-
-    let mut songs = fs::read_dir(&CONFIG.general.music_dir)
-        .expect("Failed to read music directory")
-        .map_while(Result::ok)
-        .map(|e| e.path())
-        .filter_map(|e| {
-            e.metadata()
-                .map_or(None, |m| m.modified().ok().map(|modtime| (e, modtime)))
-        })
+    let songs = index
+        .entries()
+        .filter(|e| library::in_all(&e.path))
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+
+    all_playlist.write(&songs)
+}
+
+/// Writes the most recently modified tracks, newest first
+pub fn write_recent_playlist(index: &Index) -> Result<()> {
+    let recent_playlist = Playlist::new(PathBuf::from(RECENT_PLAYLIST));
+
+    let mut songs = index
+        .entries()
+        .filter_map(|e| e.mtime.map(|modtime| (e.path.clone(), modtime)))
         .collect::<Vec<_>>();
 
     songs.sort_by_key(|(_, modtime)| modtime.to_owned());
     let songs: Vec<PathBuf> = songs.iter().rev().map(|(f, _)| f.to_owned()).collect();
     let capped = &songs[..songs.len().min(CONFIG.general.recent_length)];
-    recent_playlist.write(capped);
-    info!("Created the recent playlist")
+    recent_playlist.write(capped)
+}
+
+/// Writes the loved tracks, most recently loved first
+pub fn write_loved_playlist() -> Result<()> {
+    let loved_playlist = Playlist::new(PathBuf::from(LOVED_PLAYLIST));
+    loved_playlist.write(&loved::paths())
 }
@@ -0,0 +1,390 @@
+// src/queue.rs
+//! Logic for queueing tracks
+//!
+//! tuun remembers which of mpv's playlist entries it queued so they can be listed, removed, and
+//! reordered. mpv's playlist stays the source of truth for their order.
+
+use std::{
+    fs,
+    path::{
+        self,
+        PathBuf,
+    },
+    sync::LazyLock,
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use inotify::{
+    Inotify,
+    WatchMask,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::{
+    Value,
+    json,
+};
+use tokio::sync::Mutex;
+use tracing::{
+    debug,
+    error,
+    info,
+    instrument,
+    trace,
+};
+
+use crate::{
+    args::QueueAction,
+    ctl::exit_code,
+    ipc::{
+        self,
+        Event,
+        Request,
+    },
+    mpv,
+};
+
+pub static QUEUE_FILE: LazyLock<PathBuf> = LazyLock::new(|| PathBuf::from("/tmp/tuun/quu.tpl"));
+
+/// Playlist entry ids of the tracks tuun queued
+static QUEUED: LazyLock<Mutex<Vec<u64>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Where queued tracks should go
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Play the tracks immediately
+    Now,
+    /// Play the tracks after the current one
+    #[default]
+    Next,
+    /// Play the tracks after everything else that's queued
+    Append,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QueuedTrack {
+    /// Position in the queue, starting at 1
+    pub position: usize,
+    pub path:     String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistEntry {
+    filename: String,
+    id:       u64,
+    #[serde(default)]
+    current:  bool,
+}
+
+async fn playlist() -> Result<Vec<PlaylistEntry>> {
+    let response = mpv::command(json!(["get_property", "playlist"])).await?;
+    let playlist = response.get("data").cloned().unwrap_or(Value::Null);
+    Ok(serde_json::from_value(playlist)?)
+}
+
+fn current_index(playlist: &[PlaylistEntry]) -> usize {
+    playlist.iter().position(|e| e.current).unwrap_or(0)
+}
+
+/// # Description
+/// Returns the queued tracks that have yet to play, along with their index in mpv's playlist.
+///
+/// Tracks that already played or were removed from mpv's playlist are forgotten.
+async fn upcoming() -> Result<Vec<(usize, PlaylistEntry)>> {
+    let playlist = playlist().await?;
+    let current = current_index(&playlist);
+
+    let mut queued = QUEUED.lock().await;
+    let upcoming = playlist
+        .into_iter()
+        .enumerate()
+        .skip(current + 1)
+        .filter(|(_, e)| queued.contains(&e.id))
+        .collect::<Vec<_>>();
+
+    queued.retain(|id| upcoming.iter().any(|(_, e)| e.id == *id));
+    drop(queued);
+
+    Ok(upcoming)
+}
+
+/// Returns the playlist index of the queued track at a position
+async fn index_of(position: usize) -> Result<usize> {
+    let upcoming = upcoming().await?;
+    let Some((index, _)) = position.checked_sub(1).and_then(|i| upcoming.get(i)) else {
+        bail!("No queued track at position {position}");
+    };
+    Ok(*index)
+}
+
+/// Loads a track into mpv's playlist and returns its playlist entry id
+async fn loadfile(path: &str, flag: &str) -> Result<u64> {
+    let response = mpv::command(json!(["loadfile", path, flag])).await?;
+    if let Some(id) = response
+        .pointer("/data/playlist_entry_id")
+        .and_then(Value::as_u64)
+    {
+        return Ok(id);
+    }
+
+    // Older versions of mpv don't report the new entry's id, so fall back to the newest entry
+    // with this path
+    playlist()
+        .await?
+        .into_iter()
+        .filter(|e| e.filename == path)
+        .map(|e| e.id)
+        .max()
+        .with_context(|| format!("Couldn't find '{path}' in mpv's playlist"))
+}
+
+pub async fn list() -> Result<Vec<QueuedTrack>> {
+    Ok(upcoming()
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, e))| QueuedTrack {
+            position: i + 1,
+            path:     e.filename,
+        })
+        .collect())
+}
+
+/// Loads a track into mpv's playlist just after the entry at `anchor` and returns its playlist
+/// entry id
+async fn insert_after(path: &str, anchor: usize) -> Result<u64> {
+    // Appending puts the track after the entire playlist, so move it into place. This is used
+    // instead of `insert-at` since that needs a recent mpv.
+    let id = loadfile(path, "append").await?;
+    let appended = playlist().await?.len() - 1;
+    if appended != anchor + 1 {
+        mpv::command(json!(["playlist-move", appended, anchor + 1])).await?;
+    }
+    Ok(id)
+}
+
+/// # Description
+/// Queues tracks.
+///
+/// Tracks keep the order they're given in, so queueing A, B, and C plays A, B, then C.
+#[instrument]
+pub async fn add(tracks: &[String], mode: Mode) -> Result<()> {
+    let mut previous = None;
+    for track in tracks {
+        let playlist = playlist().await?;
+        let anchor = match (mode, previous) {
+            // Each track goes after the one queued before it
+            | (_, Some(previous)) => playlist
+                .iter()
+                .position(|e| e.id == previous)
+                .context("Previously queued track vanished from mpv's playlist")?,
+            | (Mode::Now | Mode::Next, None) => current_index(&playlist),
+            | (Mode::Append, None) => match upcoming().await?.last() {
+                | Some((index, _)) => *index,
+                | None => current_index(&playlist),
+            },
+        };
+
+        let id = insert_after(track, anchor).await?;
+        previous = Some(id);
+
+        QUEUED.lock().await.push(id);
+        info!("Queued {track}");
+    }
+
+    if mode == Mode::Now {
+        mpv::command(json!(["playlist-next"])).await?;
+    }
+
+    ipc::emit(Event::Queued { tracks: tracks.to_vec() });
+    Ok(())
+}
+
+/// Removes the queued track at a position
+#[instrument]
+pub async fn remove(position: usize) -> Result<()> {
+    let index = index_of(position).await?;
+    mpv::command(json!(["playlist-remove", index])).await?;
+    info!("Removed queued track at position {position}");
+    Ok(())
+}
+
+/// Moves the queued track at one position to another
+#[instrument]
+pub async fn reorder(from: usize, to: usize) -> Result<()> {
+    let from_index = index_of(from).await?;
+    let to_index = index_of(to).await?;
+
+    // mpv moves the entry to where the target entry is, which is one too early when moving down
+    let target = if from_index < to_index { to_index + 1 } else { to_index };
+    mpv::command(json!(["playlist-move", from_index, target])).await?;
+    info!("Moved queued track from position {from} to {to}");
+    Ok(())
+}
+
+/// Removes every queued track
+#[instrument]
+pub async fn clear() -> Result<()> {
+    // Remove from the back so earlier indices stay valid
+    for (index, _) in upcoming().await?.into_iter().rev() {
+        mpv::command(json!(["playlist-remove", index])).await?;
+    }
+
+    QUEUED.lock().await.clear();
+    info!("Cleared the queue");
+    Ok(())
+}
+
+/// # Description
+/// Queues the tracks listed in `quu.tpl`, then removes it.
+///
+/// Returns whether anything was queued.
+#[instrument]
+pub async fn ingest() -> Result<bool> {
+    let queue = &*QUEUE_FILE;
+
+    trace!("Checking whether queue {queue:?} exists...");
+    if !queue.exists() {
+        trace!("No songs queued");
+        return Ok(false);
+    }
+
+    let songs = fs::read_to_string(queue)?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    add(&songs, Mode::Next).await?;
+
+    fs::remove_file(queue)?;
+    debug!("Removed queue file {queue:?}");
+    Ok(true)
+}
+
+/// # Description
+/// Watches for `quu.tpl` being written or moved into place and queues its tracks.
+///
+/// `quu.sh` writes `_quu.tpl` and then renames it, so both events are handled.
+#[instrument]
+pub async fn watch() -> Result<()> {
+    let queue = &*QUEUE_FILE;
+    let dir = queue.parent().context("Queue file should have a parent")?;
+
+    let inotify = Inotify::init()?;
+    inotify
+        .watches()
+        .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+    let mut events = inotify.into_event_stream([0; 1024])?;
+    debug!("Watching {} for queued tracks", dir.display());
+
+    while let Some(event) = events.next().await {
+        let event = event?;
+        if event.name.as_deref() != queue.file_name() {
+            continue;
+        }
+
+        debug!("Detected queue file {queue:?}");
+        if let Err(e) = ingest().await {
+            error!("Failed to queue tracks: {e:#}");
+        }
+    }
+
+    Ok(())
+}
+
+/// # Description
+/// Runs a queue action against the running instance and returns an exit code
+pub async fn run(action: &QueueAction) -> i32 { exit_code(manage(action).await, ipc::SOCK_PATH) }
+
+async fn manage(action: &QueueAction) -> Result<()> {
+    let request = match action {
+        | QueueAction::List { json } => {
+            let queued = ipc::request(&Request::QueueList).await?.unwrap_or_default();
+            if *json {
+                println!("{queued}");
+                return Ok(());
+            }
+
+            for track in serde_json::from_value::<Vec<QueuedTrack>>(queued)? {
+                println!("{:>3}. {}", track.position, track.path);
+            }
+            return Ok(());
+        },
+        | QueueAction::Add { mode, tracks } => {
+            // The running instance has its own working directory
+            let tracks = tracks
+                .iter()
+                .map(|t| path::absolute(t).map(|p| p.to_string_lossy().to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Request::Queue { tracks, mode: *mode }
+        },
+        | QueueAction::Remove { position } => Request::QueueRemove { position: *position },
+        | QueueAction::Move { from, to } => Request::QueueMove { from: *from, to: *to },
+        | QueueAction::Clear => Request::QueueClear,
+    };
+
+    ipc::request(&request).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex as StdMutex,
+    };
+
+    use super::*;
+    use crate::fake_mpv::{
+        self,
+        FakeMpv,
+    };
+
+    #[tokio::test]
+    async fn queued_tracks_keep_their_order() {
+        let fake = Arc::new(StdMutex::new(FakeMpv::with_playlist(&[
+            "playing", "unqueued",
+        ])));
+        let _served = fake_mpv::serve(&fake);
+
+        let tracks = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let filenames = || {
+            fake.lock()
+                .expect("Fake mpv lock poisoned")
+                .filenames()
+                .into_iter()
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>()
+        };
+
+        // Each track after the first is anchored to the entry queued before it
+        add(&tracks(&["A", "B", "C"]), Mode::Next)
+            .await
+            .expect("Failed to queue tracks");
+        assert_eq!(filenames(), ["playing", "A", "B", "C", "unqueued"]);
+
+        // Appending goes after everything already queued, and keeps its own order too
+        add(&tracks(&["D", "E"]), Mode::Append)
+            .await
+            .expect("Failed to queue tracks");
+        assert_eq!(filenames(), [
+            "playing", "A", "B", "C", "D", "E", "unqueued"
+        ]);
+
+        let queued = list().await.expect("Failed to list the queue");
+        assert_eq!(
+            queued.iter().map(|t| t.path.as_str()).collect::<Vec<_>>(),
+            ["A", "B", "C", "D", "E"]
+        );
+    }
+}
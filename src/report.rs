@@ -0,0 +1,573 @@
+// src/report.rs
+//! Logic for generating "wrapped"-style listening reports
+//!
+//! Reports cover a year or a month of play history and are rendered as standalone HTML or
+//! Markdown. Cover art is only ever embedded from the tracks' tags, so reports never depend on
+//! remote art urls.
+
+use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+        HashSet,
+    },
+    fmt::{
+        self,
+        Write as _,
+    },
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{
+    Result,
+    bail,
+};
+use base64::Engine;
+use chrono::{
+    Datelike,
+    NaiveDate,
+};
+use clap::ValueEnum;
+
+use crate::{
+    history::{
+        self,
+        Ending,
+        Play,
+    },
+    stats::{
+        Ranked,
+        format_duration,
+        primary_artist,
+        rank,
+        started,
+        top_albums,
+        top_artists,
+        top_tracks,
+    },
+    tags,
+};
+
+/// The span of time a report covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Year(i32),
+    Month(i32, u32),
+}
+
+impl Period {
+    fn contains(self, date: NaiveDate) -> bool {
+        match self {
+            | Self::Year(y) => date.year() == y,
+            | Self::Month(y, m) => date.year() == y && date.month() == m,
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' isn't a year like 2026 or a month like 2026-03");
+
+        match s.split_once('-') {
+            | None => s.parse().map(Self::Year).map_err(|_| invalid()),
+            | Some((year, month)) => {
+                let year = year.parse().map_err(|_| invalid())?;
+                let month = month.parse().map_err(|_| invalid())?;
+                if !(1..=12).contains(&month) {
+                    return Err(invalid());
+                }
+                Ok(Self::Month(year, month))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            | Self::Year(y) => write!(f, "{y}"),
+            | Self::Month(y, m) => write!(f, "{y}-{m:02}"),
+        }
+    }
+}
+
+/// What to render a report as
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Html,
+    Markdown,
+}
+
+/// A ranked item along with its cover art
+#[derive(Debug)]
+struct Entry {
+    ranked: Ranked,
+    /// The cover art embedded in the item's tracks, as a data url
+    cover:  Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Streak {
+    start: NaiveDate,
+    end:   NaiveDate,
+    days:  i64,
+}
+
+#[derive(Debug)]
+struct Discovery {
+    artist: String,
+    first:  NaiveDate,
+    plays:  usize,
+}
+
+#[derive(Debug)]
+struct Report {
+    period:        Period,
+    plays:         usize,
+    listened:      f64,
+    days_listened: usize,
+    streak:        Option<Streak>,
+    top_tracks:    Vec<Entry>,
+    top_artists:   Vec<Entry>,
+    top_albums:    Vec<Entry>,
+    most_skipped:  Vec<Ranked>,
+    new_artists:   Vec<Discovery>,
+}
+
+/// # Description
+/// Generates a report and returns an exit code.
+///
+/// The report is written to `output`, or printed if there isn't one. Reports are built from the
+/// history file, so tuun doesn't need to be running.
+pub fn run(period: Period, format: Format, output: Option<&PathBuf>, limit: usize) -> i32 {
+    let result = build(period, limit).and_then(|report| {
+        let rendered = match format {
+            | Format::Html => render_html(&report),
+            | Format::Markdown => render_markdown(&report),
+        };
+
+        match output {
+            | Some(path) => {
+                fs::write(path, rendered)?;
+                eprintln!("Wrote report for {period} to {}", path.display());
+            },
+            | None => print!("{rendered}"),
+        }
+        Ok(())
+    });
+
+    match result {
+        | Ok(()) => 0,
+        | Err(e) => {
+            eprintln!("tuun: {e:#}");
+            1
+        },
+    }
+}
+
+fn build(period: Period, limit: usize) -> Result<Report> {
+    let history = history::load()?;
+    let plays = history
+        .iter()
+        .filter(|p| started(p).is_some_and(|t| period.contains(t.date_naive())))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if plays.is_empty() {
+        bail!("No plays were recorded in {period}");
+    }
+
+    let days = plays
+        .iter()
+        .filter(|p| p.counts())
+        .filter_map(|p| started(p).map(|t| t.date_naive()))
+        .collect::<BTreeSet<_>>();
+
+    let top_tracks = top_tracks(&plays, limit)
+        .into_iter()
+        .map(|ranked| {
+            let cover = cover(&plays, |p| {
+                p.title == ranked.name && Some(&p.artist) == ranked.artist.as_ref()
+            });
+            Entry { ranked, cover }
+        })
+        .collect();
+
+    let top_albums = top_albums(&plays, limit)
+        .into_iter()
+        .map(|ranked| {
+            let cover = cover(&plays, |p| {
+                p.album == ranked.name && ranked.artist.as_ref() == Some(&primary_artist(p))
+            });
+            Entry { ranked, cover }
+        })
+        .collect();
+
+    let top_artists = top_artists(&plays, limit)
+        .into_iter()
+        .map(|ranked| {
+            let cover = cover(&plays, |p| primary_artist(p) == ranked.name);
+            Entry { ranked, cover }
+        })
+        .collect();
+
+    Ok(Report {
+        period,
+        plays: plays.iter().filter(|p| p.counts()).count(),
+        listened: plays.iter().map(|p| p.listened).sum(),
+        days_listened: days.len(),
+        streak: longest_streak(&days),
+        top_tracks,
+        top_artists,
+        top_albums,
+        most_skipped: most_skipped(&plays, limit),
+        new_artists: new_artists(&history, period, limit),
+    })
+}
+
+/// Finds embedded cover art for the most recent play matching a predicate that has any
+fn cover(plays: &[Play], matches: impl Fn(&Play) -> bool) -> Option<String> {
+    let mut tried = HashSet::new();
+    let picture = plays
+        .iter()
+        .rev()
+        .filter(|p| matches(p))
+        .filter_map(|p| p.path.as_ref())
+        .filter(|path| tried.insert(*path))
+        .find_map(|path| tags::read_picture(path))?;
+
+    let data = base64::engine::general_purpose::STANDARD.encode(&picture.data);
+    Some(format!("data:{};base64,{data}", picture.mime_type))
+}
+
+fn longest_streak(days: &BTreeSet<NaiveDate>) -> Option<Streak> {
+    let mut longest: Option<Streak> = None;
+    let mut current: Option<Streak> = None;
+
+    for &day in days {
+        current = match current {
+            | Some(s) if s.end.succ_opt() == Some(day) => {
+                Some(Streak { end: day, days: s.days + 1, ..s })
+            },
+            | _ => Some(Streak { start: day, end: day, days: 1 }),
+        };
+
+        if let Some(c) = current
+            && longest.as_ref().is_none_or(|l| c.days > l.days)
+        {
+            longest = Some(c);
+        }
+    }
+
+    longest
+}
+
+/// Ranks tracks by how often they were skipped
+fn most_skipped(plays: &[Play], limit: usize) -> Vec<Ranked> {
+    let skips = plays.iter().filter(|p| p.ending == Ending::Skipped);
+    rank(skips, limit, |p| {
+        Some((
+            (p.artist.clone(), p.title.clone()),
+            p.title.clone(),
+            Some(p.artist.clone()),
+        ))
+    })
+}
+
+/// Finds the artists whose first play ever was in the period
+fn new_artists(history: &[Play], period: Period, limit: usize) -> Vec<Discovery> {
+    let mut first: HashMap<String, NaiveDate> = HashMap::new();
+    let mut plays: HashMap<String, usize> = HashMap::new();
+
+    for play in history.iter().filter(|p| p.counts()) {
+        let Some(date) = started(play).map(|t| t.date_naive()) else {
+            continue;
+        };
+        let artist = primary_artist(play);
+
+        if period.contains(date) {
+            *plays.entry(artist.clone()).or_insert(0) += 1;
+        }
+        first
+            .entry(artist)
+            .and_modify(|d| *d = (*d).min(date))
+            .or_insert(date);
+    }
+
+    let mut discoveries = first
+        .into_iter()
+        .filter(|(_, date)| period.contains(*date))
+        .map(|(artist, first)| Discovery {
+            plays: plays.get(&artist).copied().unwrap_or(0),
+            artist,
+            first,
+        })
+        .collect::<Vec<_>>();
+
+    discoveries.sort_by(|a, b| b.plays.cmp(&a.plays).then(a.first.cmp(&b.first)));
+    discoveries.truncate(limit);
+    discoveries
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn escape_markdown(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '!'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn display_name(ranked: &Ranked) -> String {
+    match &ranked.artist {
+        | Some(artist) => format!("{} - {artist}", ranked.name),
+        | None => ranked.name.clone(),
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 { format!("{n} {word}") } else { format!("{n} {word}s") }
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; background: #1b1b22; color: #e5e5f5; max-width: 48em; \
+margin: 2em auto; padding: 0 1em; }
+h1, h2 { color: #cdcddd; }
+ol { padding-left: 1.5em; }
+li { margin: 0.4em 0; }
+.cover { width: 48px; height: 48px; object-fit: cover; vertical-align: middle; margin-right: \
+0.6em; border-radius: 4px; }
+.dim { color: #8888a0; }
+";
+
+// Writing to a `String` can't fail, so the results of `write!` are ignored throughout
+fn render_html(report: &Report) -> String {
+    let mut out = String::new();
+    let title = format!("tuun wrapped {}", report.period);
+
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    let _ = writeln!(
+        out,
+        "<p>{} over {}, {} listened.</p>",
+        plural(report.plays, "play"),
+        plural(report.days_listened, "day"),
+        format_duration(report.listened),
+    );
+    if let Some(s) = &report.streak {
+        let _ = writeln!(
+            out,
+            "<p>Longest streak: {} in a row, from {} to {}.</p>",
+            plural(usize::try_from(s.days).unwrap_or(0), "day"),
+            s.start,
+            s.end,
+        );
+    }
+
+    for (heading, entries) in [
+        ("Top tracks", &report.top_tracks),
+        ("Top artists", &report.top_artists),
+        ("Top albums", &report.top_albums),
+    ] {
+        let _ = writeln!(out, "<h2>{heading}</h2>\n<ol>");
+        for entry in entries {
+            let cover = entry.cover.as_ref().map_or_else(String::new, |src| {
+                format!(
+                    "<img class=\"cover\" src=\"{}\" alt=\"\">",
+                    escape_html(src)
+                )
+            });
+            let _ = writeln!(
+                out,
+                "<li>{cover}{} <span class=\"dim\">{}, {}</span></li>",
+                escape_html(&display_name(&entry.ranked)),
+                plural(entry.ranked.plays, "play"),
+                format_duration(entry.ranked.listened),
+            );
+        }
+        let _ = writeln!(out, "</ol>");
+    }
+
+    if !report.most_skipped.is_empty() {
+        let _ = writeln!(out, "<h2>Most skipped</h2>\n<ol>");
+        for ranked in &report.most_skipped {
+            let _ = writeln!(
+                out,
+                "<li>{} <span class=\"dim\">skipped {}</span></li>",
+                escape_html(&display_name(ranked)),
+                plural(ranked.plays, "time"),
+            );
+        }
+        let _ = writeln!(out, "</ol>");
+    }
+
+    if !report.new_artists.is_empty() {
+        let _ = writeln!(out, "<h2>New artists</h2>\n<ol>");
+        for d in &report.new_artists {
+            let _ = writeln!(
+                out,
+                "<li>{} <span class=\"dim\">first played {}, {}</span></li>",
+                escape_html(&d.artist),
+                d.first,
+                plural(d.plays, "play"),
+            );
+        }
+        let _ = writeln!(out, "</ol>");
+    }
+
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+fn render_markdown(report: &Report) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# tuun wrapped {}\n", report.period);
+    let _ = writeln!(
+        out,
+        "{} over {}, {} listened.",
+        plural(report.plays, "play"),
+        plural(report.days_listened, "day"),
+        format_duration(report.listened),
+    );
+    if let Some(s) = &report.streak {
+        let _ = writeln!(
+            out,
+            "\nLongest streak: {} in a row, from {} to {}.",
+            plural(usize::try_from(s.days).unwrap_or(0), "day"),
+            s.start,
+            s.end,
+        );
+    }
+
+    for (heading, entries) in [
+        ("Top tracks", &report.top_tracks),
+        ("Top artists", &report.top_artists),
+        ("Top albums", &report.top_albums),
+    ] {
+        let _ = writeln!(out, "\n## {heading}\n");
+        for (i, entry) in entries.iter().enumerate() {
+            let cover = entry.cover.as_ref().map_or_else(String::new, |src| {
+                format!("<img src=\"{}\" width=\"48\"> ", escape_html(src))
+            });
+            let _ = writeln!(
+                out,
+                "{}. {cover}{} ({}, {})",
+                i + 1,
+                escape_markdown(&display_name(&entry.ranked)),
+                plural(entry.ranked.plays, "play"),
+                format_duration(entry.ranked.listened),
+            );
+        }
+    }
+
+    if !report.most_skipped.is_empty() {
+        let _ = writeln!(out, "\n## Most skipped\n");
+        for (i, ranked) in report.most_skipped.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{}. {} (skipped {})",
+                i + 1,
+                escape_markdown(&display_name(ranked)),
+                plural(ranked.plays, "time"),
+            );
+        }
+    }
+
+    if !report.new_artists.is_empty() {
+        let _ = writeln!(out, "\n## New artists\n");
+        for (i, d) in report.new_artists.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{}. {} (first played {}, {})",
+                i + 1,
+                escape_markdown(&d.artist),
+                d.first,
+                plural(d.plays, "play"),
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).expect("Valid date")
+    }
+
+    fn streak(days: &[NaiveDate]) -> Option<(NaiveDate, NaiveDate, i64)> {
+        longest_streak(&days.iter().copied().collect()).map(|s| (s.start, s.end, s.days))
+    }
+
+    #[test]
+    fn periods_are_years_or_months() {
+        assert_eq!("2026".parse(), Ok(Period::Year(2026)));
+        assert_eq!("2026-03".parse(), Ok(Period::Month(2026, 3)));
+        assert_eq!("2026-12".parse(), Ok(Period::Month(2026, 12)));
+        assert_eq!(Period::Month(2026, 3).to_string(), "2026-03");
+    }
+
+    #[test]
+    fn invalid_periods_are_rejected() {
+        for s in ["2026-13", "2026-00", "2026-", "-03", "abc", "2026-abc", ""] {
+            assert!(s.parse::<Period>().is_err(), "'{s}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn streaks_carry_across_months_and_years() {
+        assert_eq!(
+            streak(&[date(2025, 12, 30), date(2025, 12, 31), date(2026, 1, 1)]),
+            Some((date(2025, 12, 30), date(2026, 1, 1), 3))
+        );
+        assert_eq!(
+            streak(&[date(2026, 2, 28), date(2026, 3, 1)]),
+            Some((date(2026, 2, 28), date(2026, 3, 1), 2))
+        );
+    }
+
+    #[test]
+    fn streaks_break_on_missed_days() {
+        // 2028 is a leap year, so going from Feb 28 to Mar 1 misses a day
+        assert_eq!(
+            streak(&[
+                date(2026, 1, 30),
+                date(2026, 1, 31),
+                date(2026, 2, 2),
+                date(2028, 2, 28),
+                date(2028, 3, 1),
+            ]),
+            Some((date(2026, 1, 30), date(2026, 1, 31), 2))
+        );
+        // The earlier of equally long streaks wins
+        assert_eq!(
+            streak(&[date(2025, 12, 31), date(2026, 1, 2)]),
+            Some((date(2025, 12, 31), date(2025, 12, 31), 1))
+        );
+        assert_eq!(streak(&[]), None);
+    }
+}
@@ -0,0 +1,436 @@
+// src/scrobbler.rs
+//! Logic for sending plays to scrobbling services
+//!
+//! Every enabled service is a [`Scrobbler`]. Now playing updates and scrobbles go to all of them at
+//! once, and each service keeps its own tally of successes and failures. A failed scrobble is
+//! spooled for the service that failed it alone.
+//!
+//! Last.fm and Libre.fm (or any GNU FM instance) speak the Audioscrobbler API, while `ListenBrainz`
+//! and Maloja speak the `ListenBrainz` API.
+
+use std::{
+    fmt,
+    sync::{
+        LazyLock,
+        Mutex,
+        PoisonError,
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+    },
+};
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    instrument,
+};
+
+use crate::{
+    CONFIG,
+    config::ScrobbleRule,
+    ipc::{
+        self,
+        Event,
+    },
+    lastfm::Audioscrobbler,
+    listenbrainz::ListenBrainz,
+    mpv::SCROBBLED,
+    spool,
+    structs::Track,
+};
+
+/// Last.fm doesn't scrobble tracks this short, in seconds
+const MIN_DURATION: f64 = 30.;
+
+/// Last.fm scrobbles a track after this many seconds, even if it's less than halfway through
+const MAX_THRESHOLD: f64 = 240.;
+
+/// # Description
+/// Whether a track has been listened to long enough to scrobble.
+///
+/// By default, that's `scrobble_percent` of the track. Last.fm's rules instead need the track to
+/// be longer than 30 seconds, and to have been listened to for half its length or 4 minutes,
+/// whichever comes first.
+///
+/// Only time actually listened counts, so seeking ahead doesn't make a track scrobble.
+pub fn is_eligible(duration: f64, listened: f64) -> bool {
+    eligible_under(
+        CONFIG.lastfm.scrobble_rule,
+        CONFIG.lastfm.scrobble_percent,
+        duration,
+        listened,
+    )
+}
+
+/// Whether a track has been listened to long enough to scrobble under `rule`
+fn eligible_under(rule: ScrobbleRule, percent: u8, duration: f64, listened: f64) -> bool {
+    match rule {
+        | ScrobbleRule::Percent => listened >= duration * (f64::from(percent) / 100.),
+        | ScrobbleRule::LastFM => {
+            duration > MIN_DURATION && listened >= (duration / 2.).min(MAX_THRESHOLD)
+        },
+    }
+}
+
+/// A play of a track, as sent to scrobbling services
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Scrobble {
+    pub artist:       String,
+    pub title:        String,
+    pub album:        String,
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    /// Track duration in seconds
+    #[serde(default)]
+    pub duration:     Option<u64>,
+    /// Unix timestamp of when the track started playing
+    pub timestamp:    u64,
+}
+
+impl Scrobble {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(track: &Track, timestamp: u64) -> Self {
+        Self {
+            artist: track.get_primary_artist(),
+            title: track.title.clone(),
+            album: track.album.clone(),
+            album_artist: track.album_artist.clone(),
+            track_number: track.track_number,
+            duration: (track.duration > 0.).then(|| track.duration.round() as u64),
+            timestamp,
+        }
+    }
+
+    /// The album, unless it's unknown
+    pub fn known_album(&self) -> Option<&str> {
+        Some(self.album.as_str()).filter(|a| !a.is_empty() && *a != "<Unknown album>")
+    }
+}
+
+/// # Description
+/// A scrobbling service.
+///
+/// Methods block, so they're only ever called from `spawn_blocking`.
+pub trait Scrobbler: Send + Sync {
+    /// The most scrobbles the service accepts in one request
+    fn batch_size(&self) -> usize;
+
+    /// Gets ready to scrobble, such as by authenticating
+    fn connect(&self) -> Result<()> { Ok(()) }
+
+    fn now_playing(&self, scrobble: &Scrobble) -> Result<()>;
+
+    /// Submits up to [`Self::batch_size`] scrobbles at once
+    fn scrobble(&self, scrobbles: &[Scrobble]) -> Result<()>;
+
+    /// Whether the service keeps loved tracks
+    fn can_love(&self) -> bool { false }
+
+    /// Loves or unloves a track
+    fn love(&self, _scrobble: &Scrobble, _loved: bool) -> Result<()> {
+        bail!("Loving tracks isn't supported")
+    }
+}
+
+/// A scrobbling service tuun knows about
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    #[default]
+    LastFM,
+    LibreFM,
+    ListenBrainz,
+    Maloja,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            | Self::LastFM => "Last.fm",
+            | Self::LibreFM => "Libre.fm",
+            | Self::ListenBrainz => "ListenBrainz",
+            | Self::Maloja => "Maloja",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An enabled scrobbling service and how it's been doing
+struct Service {
+    target:     Target,
+    scrobbler:  Box<dyn Scrobbler>,
+    scrobbled:  AtomicU64,
+    failed:     AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Service {
+    fn new(target: Target, scrobbler: impl Scrobbler + 'static) -> Self {
+        Self {
+            target,
+            scrobbler: Box::new(scrobbler),
+            scrobbled: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Runs a blocking call against the scrobbler off the runtime's threads
+    async fn call<T, F>(&'static self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Scrobbler) -> Result<T> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(move || f(self.scrobbler.as_ref())).await?
+    }
+
+    /// Submits scrobbles and keeps count of how it went
+    async fn submit(&'static self, batch: Vec<Scrobble>) -> Result<()> {
+        let count = batch.len() as u64;
+        let result = self.call(move |s| s.scrobble(&batch)).await;
+
+        let mut last_error = self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match &result {
+            | Ok(()) => {
+                self.scrobbled.fetch_add(count, Ordering::Relaxed);
+                *last_error = None;
+            },
+            | Err(e) => {
+                self.failed.fetch_add(count, Ordering::Relaxed);
+                *last_error = Some(format!("{e:#}"));
+            },
+        }
+        drop(last_error);
+
+        result
+    }
+}
+
+static SERVICES: LazyLock<Vec<Service>> = LazyLock::new(|| {
+    let mut services = Vec::new();
+
+    if CONFIG.lastfm.used {
+        let c = &CONFIG.lastfm;
+        services.push(Service::new(
+            Target::LastFM,
+            Audioscrobbler::new(
+                Target::LastFM,
+                crate::lastfm::API_ROOT,
+                &c.apikey,
+                &c.secret,
+                &c.user,
+                &c.password,
+            ),
+        ));
+    }
+
+    if CONFIG.librefm.used {
+        let c = &CONFIG.librefm;
+        services.push(Service::new(
+            Target::LibreFM,
+            Audioscrobbler::new(
+                Target::LibreFM,
+                &c.api_url,
+                &c.apikey,
+                &c.secret,
+                &c.user,
+                &c.password,
+            ),
+        ));
+    }
+
+    if CONFIG.listenbrainz.used {
+        let c = &CONFIG.listenbrainz;
+        services.push(Service::new(
+            Target::ListenBrainz,
+            ListenBrainz::new(&c.api_url, &c.token),
+        ));
+    }
+
+    if CONFIG.maloja.used {
+        let c = &CONFIG.maloja;
+        services.push(Service::new(
+            Target::Maloja,
+            ListenBrainz::new(&c.api_url, &c.token),
+        ));
+    }
+
+    services
+});
+
+fn service(target: Target) -> Option<&'static Service> {
+    SERVICES.iter().find(|s| s.target == target)
+}
+
+/// Whether any scrobbling service is enabled
+pub fn any_used() -> bool { !SERVICES.is_empty() }
+
+/// Whether a scrobbling service is enabled
+pub fn used(target: Target) -> bool { service(target).is_some() }
+
+/// The most scrobbles a service accepts in one request
+pub fn batch_size(target: Target) -> usize {
+    service(target).map_or(1, |s| s.scrobbler.batch_size())
+}
+
+/// Gets every service ready to scrobble in the background
+pub fn connect() {
+    for service in SERVICES.iter() {
+        tokio::spawn(async move {
+            match service.call(|s| s.connect()).await {
+                | Ok(()) => info!("Connected to {}", service.target),
+                | Err(e) => error!("Failed to connect to {}: {e:#}", service.target),
+            }
+        });
+    }
+}
+
+/// Tells every service what's playing
+#[instrument(skip(scrobble))]
+pub fn now_playing(scrobble: &Scrobble) {
+    for service in SERVICES.iter() {
+        let scrobble = scrobble.clone();
+        tokio::spawn(async move {
+            match service.call(move |s| s.now_playing(&scrobble)).await {
+                | Ok(()) => debug!("Set {} now playing", service.target),
+                | Err(e) => error!("Failed to set {} now playing: {e:#}", service.target),
+            }
+        });
+    }
+}
+
+/// Scrobbles to every service, spooling the scrobble for any that fail
+#[instrument(skip(scrobble, track))]
+pub fn scrobble(scrobble: &Scrobble, track: &Track) {
+    for service in SERVICES.iter() {
+        let scrobble = scrobble.clone();
+        let track = track.clone();
+        tokio::spawn(async move {
+            match service.submit(vec![scrobble.clone()]).await {
+                | Ok(()) => {
+                    info!("Scrobbled '{track}' to {}", service.target);
+                    SCROBBLED.store(true, Ordering::Relaxed);
+                    ipc::emit(Event::Scrobbled { track, target: service.target });
+                    spool::wake();
+                },
+                | Err(e) => {
+                    error!("Failed to scrobble '{track}' to {}: {e:#}", service.target);
+                    spool::push(service.target, scrobble).await;
+                },
+            }
+        });
+    }
+}
+
+/// # Description
+/// Loves or unloves a track on every service that keeps loved tracks.
+///
+/// Every service is tried, and their failures are reported together.
+pub async fn love(scrobble: &Scrobble, loved: bool) -> Result<()> {
+    let mut failures = Vec::new();
+    for service in SERVICES.iter().filter(|s| s.scrobbler.can_love()) {
+        let scrobble = scrobble.clone();
+        match service.call(move |s| s.love(&scrobble, loved)).await {
+            | Ok(()) => info!("Synced loved track to {}", service.target),
+            | Err(e) => {
+                error!("Failed to sync loved track to {}: {e:#}", service.target);
+                failures.push(format!("{}: {e:#}", service.target));
+            },
+        }
+    }
+
+    if !failures.is_empty() {
+        bail!("{}", failures.join("; "));
+    }
+    Ok(())
+}
+
+/// Submits spooled scrobbles to a service
+pub async fn submit(target: Target, batch: Vec<Scrobble>) -> Result<()> {
+    service(target)
+        .with_context(|| format!("{target} isn't enabled"))?
+        .submit(batch)
+        .await
+}
+
+/// How a scrobbling service has been doing since tuun started
+#[derive(Serialize, Debug)]
+pub struct ScrobblerStatus {
+    pub target:     Target,
+    pub scrobbled:  u64,
+    pub failed:     u64,
+    /// The most recent error, if the last scrobble failed
+    pub last_error: Option<String>,
+}
+
+pub fn statuses() -> Vec<ScrobblerStatus> {
+    SERVICES
+        .iter()
+        .map(|s| ScrobblerStatus {
+            target:     s.target,
+            scrobbled:  s.scrobbled.load(Ordering::Relaxed),
+            failed:     s.failed.load(Ordering::Relaxed),
+            last_error: s
+                .last_error
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lastfm(duration: f64, listened: f64) -> bool {
+        eligible_under(ScrobbleRule::LastFM, 44, duration, listened)
+    }
+
+    #[test]
+    fn lastfm_needs_half_the_track() {
+        assert!(!lastfm(200., 99.9));
+        assert!(lastfm(200., 100.));
+    }
+
+    #[test]
+    fn lastfm_needs_at_most_four_minutes() {
+        assert!(!lastfm(600., 239.9));
+        assert!(lastfm(600., 240.));
+        // Exactly 8 minutes is where half and 4 minutes meet
+        assert!(!lastfm(480., 239.9));
+        assert!(lastfm(480., 240.));
+    }
+
+    #[test]
+    fn lastfm_never_scrobbles_short_tracks() {
+        assert!(!lastfm(29., 29.));
+        assert!(!lastfm(30., 30.));
+        assert!(lastfm(30.5, 15.25));
+    }
+
+    #[test]
+    fn percent_needs_that_much_of_the_track() {
+        assert!(!eligible_under(ScrobbleRule::Percent, 44, 100., 43.9));
+        assert!(eligible_under(ScrobbleRule::Percent, 44, 100., 44.));
+        // Short tracks still scrobble under the percent rule
+        assert!(eligible_under(ScrobbleRule::Percent, 44, 10., 5.));
+    }
+}
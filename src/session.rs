@@ -0,0 +1,279 @@
+// src/session.rs
+//! Logic for tracking the play in progress
+//!
+//! A play starts when mpv starts a file, and ends when mpv moves on from it or quits. A looped
+//! track starts a new play each time it wraps back around to the start.
+//!
+//! While a play is in progress, its session sums the wall-clock time spent actually playing. Time
+//! spent paused doesn't count, and neither do seeks, so jumping ahead can't make a track scrobble
+//! and seeking back to the start doesn't count as playing it again. Scrobbling, history, and
+//! Discord all read from here, so they agree on when a play started and how long it's lasted.
+
+use std::{
+    mem,
+    sync::{
+        LazyLock,
+        atomic::Ordering,
+    },
+    time::Instant,
+};
+
+use tokio::sync::Mutex;
+use tracing::trace;
+
+use crate::{
+    mpv::{
+        LOOPED,
+        NOW_PLAYING_SET,
+        PAUSED,
+        SCROBBLED,
+    },
+    structs::unix_now,
+};
+
+/// mpv reports the playback time several times a second while playing, so longer gaps between
+/// reports (such as while the system was suspended) are capped at this many seconds
+const MAX_GAP: f64 = 5.;
+
+/// How close to the end and start of a looped track a jump must be to count as a loop
+const LOOP_SLACK: f64 = 2.;
+
+static SESSION: LazyLock<Mutex<Option<Session>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Debug)]
+struct Session {
+    /// Unix timestamp of when the play started
+    started:     u64,
+    /// Seconds spent playing, up until `resumed`
+    listened:    f64,
+    /// When time spent playing was last counted, or `None` while paused
+    resumed:     Option<Instant>,
+    /// The last playback position mpv reported
+    position:    f64,
+    now_playing: bool,
+    scrobbled:   bool,
+}
+
+impl Session {
+    /// Starts a session, resetting the now playing and scrobbled flags along with it
+    fn new(position: f64) -> Self {
+        // Playing from partway through means the play started that long ago
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let started = unix_now().saturating_sub(position.max(0.) as u64);
+
+        NOW_PLAYING_SET.store(false, Ordering::Relaxed);
+        SCROBBLED.store(false, Ordering::Relaxed);
+
+        Self {
+            started,
+            listened: 0.,
+            resumed: (!PAUSED.load(Ordering::Relaxed)).then(Instant::now),
+            position,
+            now_playing: false,
+            scrobbled: false,
+        }
+    }
+
+    /// Counts the time spent playing since it was last counted
+    fn bank(&mut self) {
+        if let Some(resumed) = self.resumed {
+            let now = Instant::now();
+            self.listened += (now - resumed).as_secs_f64().min(MAX_GAP);
+            self.resumed = Some(now);
+        }
+    }
+
+    /// Records a new playback position, counting the time spent playing since the last one
+    fn progress(&mut self, position: f64) {
+        self.bank();
+        self.position = position;
+    }
+
+    /// Stops or resumes counting time spent playing
+    fn pause(&mut self, paused: bool) {
+        if paused {
+            self.bank();
+            self.resumed = None;
+        } else if self.resumed.is_none() {
+            self.resumed = Some(Instant::now());
+        }
+    }
+
+    /// Whether a new playback position is a jump from the end of the track back to the start
+    fn has_wrapped(&self, position: f64, duration: f64) -> bool {
+        position <= LOOP_SLACK && self.position >= duration - LOOP_SLACK && self.position > position
+    }
+
+    fn listened(&self) -> f64 {
+        self.listened
+            + self
+                .resumed
+                .map_or(0., |r| r.elapsed().as_secs_f64().min(MAX_GAP))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            started:  self.started,
+            listened: self.listened(),
+            position: self.position,
+        }
+    }
+}
+
+/// The state of a play at some moment
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    /// Unix timestamp of when the play started
+    pub started:  u64,
+    /// Seconds the track has been playing for, excluding pauses and seeks
+    pub listened: f64,
+    /// Seconds into the track
+    pub position: f64,
+}
+
+/// Starts a new play, abandoning any in progress
+pub async fn begin() {
+    trace!("Starting a play");
+    *SESSION.lock().await = Some(Session::new(0.));
+}
+
+/// Ends the play in progress, returning how it went
+pub async fn end() -> Option<Snapshot> {
+    let session = SESSION.lock().await.take()?;
+    Some(session.snapshot())
+}
+
+/// Returns the play in progress
+pub async fn current() -> Option<Snapshot> { SESSION.lock().await.as_ref().map(Session::snapshot) }
+
+/// # Description
+/// Records a new playback position, starting a play if there isn't one.
+///
+/// Should be called whenever mpv reports a new playback time.
+pub async fn progress(position: f64) {
+    let mut session = SESSION.lock().await;
+    match session.as_mut() {
+        | Some(s) => s.progress(position),
+        | None => {
+            // tuun connected to mpv partway through a file
+            trace!("Starting a play at {position}");
+            *session = Some(Session::new(position));
+        },
+    }
+}
+
+/// Stops or resumes counting time spent playing
+pub async fn pause(paused: bool) {
+    if let Some(s) = SESSION.lock().await.as_mut() {
+        s.pause(paused);
+    }
+}
+
+/// # Description
+/// Whether a new playback position means a looped track wrapped back around to the start.
+///
+/// A jump back to the start only counts if it came from the end of the track, so seeking back
+/// during a looped track isn't mistaken for a loop.
+pub async fn has_looped(position: f64, duration: f64) -> bool {
+    if !LOOPED.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    SESSION
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|s| s.has_wrapped(position, duration))
+}
+
+/// Marks the play as announced as now playing, returning whether it wasn't already
+pub async fn mark_now_playing() -> bool {
+    SESSION.lock().await.as_mut().is_some_and(|s| {
+        NOW_PLAYING_SET.store(true, Ordering::Relaxed);
+        !mem::replace(&mut s.now_playing, true)
+    })
+}
+
+/// Marks the play as scrobbled, returning whether it wasn't already
+pub async fn mark_scrobbled() -> bool {
+    SESSION
+        .lock()
+        .await
+        .as_mut()
+        .is_some_and(|s| !mem::replace(&mut s.scrobbled, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A session at `position` that's been playing for `playing` seconds since it was last counted
+    fn playing_for(position: f64, playing: u64) -> Session {
+        Session {
+            started: 0,
+            listened: 0.,
+            resumed: Instant::now().checked_sub(Duration::from_secs(playing)),
+            position,
+            now_playing: false,
+            scrobbled: false,
+        }
+    }
+
+    /// Whether `listened` is `expected` seconds, give or take the time the test took
+    fn about(listened: f64, expected: f64) -> bool {
+        (expected..expected + 0.5).contains(&listened)
+    }
+
+    #[test]
+    fn pauses_stop_the_clock() {
+        let mut session = playing_for(0., 3);
+        session.pause(true);
+        assert!(session.resumed.is_none());
+        assert!(about(session.listened(), 3.));
+
+        // Progress while paused counts nothing
+        session.progress(3.);
+        assert!(about(session.listened(), 3.));
+
+        session.pause(false);
+        assert!(session.resumed.is_some());
+        assert!(about(session.listened(), 3.));
+    }
+
+    #[test]
+    fn resuming_twice_keeps_the_time_played() {
+        let mut session = playing_for(0., 3);
+        session.pause(false);
+        assert!(about(session.listened(), 3.));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn seeking_back_counts_only_time_played() {
+        let mut session = playing_for(100., 2);
+        session.progress(10.);
+        assert_eq!(session.position, 10.);
+        assert!(about(session.listened(), 2.));
+        assert!(!session.has_wrapped(10., 200.));
+    }
+
+    #[test]
+    fn loops_wrap_from_the_end_to_the_start() {
+        assert!(playing_for(199., 0).has_wrapped(0.5, 200.));
+        // Seeking back to the start from partway through isn't a loop
+        assert!(!playing_for(150., 0).has_wrapped(0.5, 200.));
+        // Neither is jumping from the end to somewhere past the start
+        assert!(!playing_for(199., 0).has_wrapped(30., 200.));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn gaps_are_capped() {
+        let mut session = playing_for(0., 60);
+        assert_eq!(session.listened(), MAX_GAP);
+        session.progress(1.);
+        assert_eq!(session.listened, MAX_GAP);
+    }
+}
@@ -0,0 +1,393 @@
+// src/spool.rs
+//! Logic for keeping scrobbles that couldn't be submitted
+//!
+//! Scrobbles that fail are kept in `$XDG_STATE_HOME/tuun/spool.json` along with when they were
+//! played and where they were headed, and retried with exponential backoff. Retries are submitted
+//! in batches once the scrobbler has (re)authenticated.
+//!
+//! A scrobble only leaves the spool after its service accepts it, and the spool is saved after
+//! every change, so scrobbles survive restarts without being submitted twice.
+
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::LazyLock,
+    time::Duration,
+};
+
+use anyhow::{
+    Context,
+    Result,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    sync::{
+        Mutex,
+        Notify,
+    },
+    time::timeout,
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    instrument,
+    warn,
+};
+
+use crate::{
+    config::get_state_dir,
+    scrobbler::{
+        self,
+        Scrobble,
+        Target,
+    },
+    structs::unix_now,
+};
+
+/// Last.fm ignores scrobbles older than this
+const MAX_AGE: Duration = Duration::from_hours(14 * 24);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_hours(1);
+
+static SPOOL: LazyLock<Mutex<Vec<Spooled>>> = LazyLock::new(|| Mutex::new(load()));
+
+/// Wakes the retry loop when scrobbles are spooled or the scrobbler recovers
+static WAKE: Notify = Notify::const_new();
+
+/// A scrobble and where it should be submitted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Spooled {
+    /// Scrobbles spooled before targets existed were all for Last.fm
+    #[serde(default)]
+    target:   Target,
+    #[serde(flatten)]
+    scrobble: Scrobble,
+}
+
+fn spool_file() -> Option<PathBuf> { get_state_dir().map(|d| d.join("tuun/spool.json")) }
+
+fn load() -> Vec<Spooled> {
+    let Some(path) = spool_file() else {
+        warn!("Couldn't find a state directory for the scrobble spool");
+        return Vec::new();
+    };
+    load_from(&path)
+}
+
+/// Reads a spool from disk, treating a missing or unreadable spool as empty
+fn load_from(path: &Path) -> Vec<Spooled> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    match serde_json::from_str::<Vec<Spooled>>(&contents) {
+        | Ok(spool) => {
+            debug!("Loaded {} spooled scrobbles", spool.len());
+            spool
+        },
+        | Err(e) => {
+            error!(
+                "Failed to parse scrobble spool at '{}': {e}",
+                path.display()
+            );
+            Vec::new()
+        },
+    }
+}
+
+fn save(spool: &[Spooled]) -> Result<()> {
+    let path = spool_file().context("Couldn't find a state directory")?;
+    save_to(&path, spool)
+}
+
+fn save_to(path: &Path, spool: &[Spooled]) -> Result<()> {
+    let dir = path.parent().context("Spool path should have a parent")?;
+    fs::create_dir_all(dir)?;
+
+    // Write to a temporary file first so an interrupted save can't lose the spool
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(spool)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Adds a scrobble to a spool unless it's already there, returning whether it was added
+fn add(spool: &mut Vec<Spooled>, spooled: Spooled) -> bool {
+    if spool.contains(&spooled) {
+        return false;
+    }
+    spool.push(spooled);
+    true
+}
+
+/// # Description
+/// Drops scrobbles too old for Audioscrobbler services to accept, returning how many were dropped.
+///
+/// Other services accept scrobbles of any age, so theirs are kept.
+fn remove_stale(spool: &mut Vec<Spooled>, now: u64) -> usize {
+    let cutoff = now.saturating_sub(MAX_AGE.as_secs());
+    let before = spool.len();
+    spool.retain(|s| {
+        !matches!(s.target, Target::LastFM | Target::LibreFM) || s.scrobble.timestamp >= cutoff
+    });
+    before - spool.len()
+}
+
+/// How long to wait before the next retry, after one succeeded or failed
+fn next_backoff(backoff: Duration, succeeded: bool) -> Duration {
+    if succeeded { MIN_BACKOFF } else { (backoff * 2).min(MAX_BACKOFF) }
+}
+
+/// Adds a scrobble to the spool to be retried later
+#[instrument]
+pub async fn push(target: Target, scrobble: Scrobble) {
+    let mut spool = SPOOL.lock().await;
+    let was_empty = spool.is_empty();
+    if !add(&mut spool, Spooled { target, scrobble }) {
+        debug!("Scrobble is already spooled");
+        return;
+    }
+
+    if let Err(e) = save(&spool) {
+        error!("Failed to save scrobble spool: {e:#}");
+    }
+    info!("Spooled scrobble for {target} ({} waiting)", spool.len());
+    drop(spool);
+
+    if was_empty {
+        WAKE.notify_one();
+    }
+}
+
+/// Retries spooled scrobbles now instead of waiting out the backoff
+pub fn wake() { WAKE.notify_one(); }
+
+/// # Description
+/// Retries spooled scrobbles until the spool is empty, then waits for more.
+///
+/// Failed retries back off exponentially, from 30 seconds up to an hour.
+#[instrument]
+pub async fn retry() {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        // Scrobbles for targets that have since been disabled are kept, but not retried
+        while !SPOOL.lock().await.iter().any(|s| scrobbler::used(s.target)) {
+            WAKE.notified().await;
+        }
+
+        // Wait out the backoff, unless the scrobbler recovers first
+        let _ = timeout(backoff, WAKE.notified()).await;
+
+        // Each service's failures are logged as they happen
+        let flushed = flush().await.is_ok();
+        backoff = next_backoff(backoff, flushed);
+        if !flushed {
+            warn!(
+                "Failed to submit spooled scrobbles, retrying in {}s",
+                backoff.as_secs()
+            );
+        }
+    }
+}
+
+/// Submits everything in the spool in batches, one target at a time
+async fn flush() -> Result<()> {
+    drop_stale().await;
+
+    // Keep going after a failure so one service being down doesn't hold up the rest
+    let mut result = Ok(());
+    for target in [
+        Target::LastFM,
+        Target::LibreFM,
+        Target::ListenBrainz,
+        Target::Maloja,
+    ] {
+        if scrobbler::used(target)
+            && let Err(e) = flush_target(target).await
+        {
+            warn!("{e:#}");
+            result = Err(e);
+        }
+    }
+    result
+}
+
+async fn flush_target(target: Target) -> Result<()> {
+    loop {
+        let batch = SPOOL
+            .lock()
+            .await
+            .iter()
+            .filter(|s| s.target == target)
+            .take(scrobbler::batch_size(target))
+            .cloned()
+            .collect::<Vec<_>>();
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let scrobbles = batch.iter().map(|s| s.scrobble.clone()).collect();
+        scrobbler::submit(target, scrobbles)
+            .await
+            .with_context(|| format!("Failed to submit spooled scrobbles to {target}"))?;
+
+        let mut spool = SPOOL.lock().await;
+        spool.retain(|s| !batch.contains(s));
+        let saved = save(&spool);
+        let waiting = spool.len();
+        drop(spool);
+
+        saved.context("Failed to save scrobble spool")?;
+        info!(
+            "Submitted {} spooled scrobbles to {target} ({waiting} waiting)",
+            batch.len()
+        );
+    }
+}
+
+/// Drops stale scrobbles from the spool, saving it if any were dropped
+async fn drop_stale() {
+    let mut spool = SPOOL.lock().await;
+    let dropped = remove_stale(&mut spool, unix_now());
+    if dropped == 0 {
+        return;
+    }
+
+    warn!("Dropping {dropped} spooled scrobbles older than 14 days");
+    if let Err(e) = save(&spool) {
+        error!("Failed to save scrobble spool: {e:#}");
+    }
+    drop(spool);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        process,
+    };
+
+    use super::*;
+
+    const NOW: u64 = 1_750_000_000;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn spooled(target: Target, timestamp: u64) -> Spooled {
+        Spooled {
+            target,
+            scrobble: Scrobble {
+                artist: "Artist".to_owned(),
+                title: "Title".to_owned(),
+                album: "Album".to_owned(),
+                album_artist: None,
+                track_number: None,
+                duration: Some(200),
+                timestamp,
+            },
+        }
+    }
+
+    /// A spool path of its own for each test, removed when dropped
+    struct TempSpool(PathBuf);
+
+    impl TempSpool {
+        fn new(name: &str) -> Self {
+            Self(env::temp_dir().join(format!(
+                "tuun-test-spool-{}-{name}/spool.json",
+                process::id()
+            )))
+        }
+    }
+
+    impl Drop for TempSpool {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    #[test]
+    fn duplicates_are_spooled_once() {
+        let path = TempSpool::new("dedup");
+        let mut spool = Vec::new();
+        assert!(add(&mut spool, spooled(Target::LastFM, NOW)));
+        assert!(!add(&mut spool, spooled(Target::LastFM, NOW)));
+        // The same play is spooled separately for each service that failed it
+        assert!(add(&mut spool, spooled(Target::ListenBrainz, NOW)));
+        assert!(add(&mut spool, spooled(Target::LastFM, NOW + 1)));
+        save_to(&path.0, &spool).expect("Failed to save spool");
+
+        let mut loaded = load_from(&path.0);
+        assert_eq!(loaded, spool);
+        assert!(!add(&mut loaded, spooled(Target::ListenBrainz, NOW)));
+        assert_eq!(loaded.len(), 3);
+    }
+
+    #[test]
+    fn missing_or_corrupt_spools_are_empty() {
+        let path = TempSpool::new("corrupt");
+        assert!(load_from(&path.0).is_empty());
+
+        save_to(&path.0, &[]).expect("Failed to save spool");
+        fs::write(&path.0, "not json").expect("Failed to corrupt spool");
+        assert!(load_from(&path.0).is_empty());
+    }
+
+    #[test]
+    fn untargeted_scrobbles_were_for_lastfm() {
+        let path = TempSpool::new("legacy");
+        save_to(&path.0, &[]).expect("Failed to save spool");
+        fs::write(
+            &path.0,
+            r#"[{"artist":"Artist","title":"Title","album":"Album","duration":200,"timestamp":1750000000}]"#,
+        )
+        .expect("Failed to write spool");
+        assert_eq!(load_from(&path.0), [spooled(Target::LastFM, NOW)]);
+    }
+
+    #[test]
+    fn stale_audioscrobbler_scrobbles_are_dropped() {
+        let cutoff = NOW - 14 * DAY;
+        let mut spool = vec![
+            spooled(Target::LastFM, cutoff - 1),
+            spooled(Target::LibreFM, cutoff - 1),
+            spooled(Target::ListenBrainz, cutoff - 1),
+            spooled(Target::Maloja, cutoff - 1),
+            spooled(Target::LastFM, cutoff),
+            spooled(Target::LibreFM, NOW),
+        ];
+
+        assert_eq!(remove_stale(&mut spool, NOW), 2);
+        assert_eq!(spool, [
+            spooled(Target::ListenBrainz, cutoff - 1),
+            spooled(Target::Maloja, cutoff - 1),
+            spooled(Target::LastFM, cutoff),
+            spooled(Target::LibreFM, NOW),
+        ]);
+        assert_eq!(remove_stale(&mut spool, NOW), 0);
+    }
+
+    #[test]
+    fn failures_back_off_up_to_an_hour() {
+        let mut backoff = MIN_BACKOFF;
+        let mut waits = Vec::new();
+        for _ in 0..9 {
+            backoff = next_backoff(backoff, false);
+            waits.push(backoff.as_secs());
+        }
+        assert_eq!(waits, [60, 120, 240, 480, 960, 1920, 3600, 3600, 3600]);
+
+        assert_eq!(next_backoff(backoff, true), MIN_BACKOFF);
+        assert_eq!(MIN_BACKOFF.as_secs(), 30);
+    }
+}
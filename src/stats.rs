@@ -0,0 +1,456 @@
+// src/stats.rs
+//! Logic for summarizing play history
+//!
+//! Only plays that count (see [`Play::counts`]) are ranked, but listening time includes every
+//! play. Artists are grouped by their primary artist.
+
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+
+use anyhow::Result;
+use chrono::{
+    DateTime,
+    Local,
+    TimeDelta,
+    Timelike,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    history::{
+        self,
+        Play,
+    },
+    structs::Track,
+};
+
+/// How far back stats should look
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Range {
+    /// The past 7 days
+    Week,
+    /// The past 30 days
+    #[default]
+    Month,
+    /// The past 365 days
+    Year,
+    /// Everything
+    All,
+}
+
+impl Range {
+    /// Returns the earliest time in the range, if it has one
+    pub fn start(self) -> Option<DateTime<Local>> {
+        let days = match self {
+            | Self::Week => 7,
+            | Self::Month => 30,
+            | Self::Year => 365,
+            | Self::All => return None,
+        };
+        Some(Local::now() - TimeDelta::days(days))
+    }
+
+    const fn describe(self) -> &'static str {
+        match self {
+            | Self::Week => "the past week",
+            | Self::Month => "the past month",
+            | Self::Year => "the past year",
+            | Self::All => "all time",
+        }
+    }
+}
+
+/// How plays should be grouped over time
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    /// Plays on each day
+    #[default]
+    Day,
+    /// Plays in each hour of the day
+    Hour,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Ranked {
+    pub name:     String,
+    /// The artist, for albums and tracks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist:   Option<String>,
+    pub plays:    usize,
+    /// Seconds listened
+    pub listened: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Activity {
+    /// The day (`YYYY-MM-DD`) or hour (`00` to `23`)
+    pub label: String,
+    pub plays: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub range:       Range,
+    pub plays:       usize,
+    /// Seconds listened
+    pub listened:    f64,
+    pub top_artists: Vec<Ranked>,
+    pub top_albums:  Vec<Ranked>,
+    pub top_tracks:  Vec<Ranked>,
+    pub bucket:      Bucket,
+    pub activity:    Vec<Activity>,
+}
+
+/// Returns when a play started in local time
+pub fn started(play: &Play) -> Option<DateTime<Local>> {
+    DateTime::from_timestamp(i64::try_from(play.started).ok()?, 0).map(|t| t.with_timezone(&Local))
+}
+
+/// Returns the recorded plays within a range, oldest first
+pub fn plays_in(range: Range) -> Result<Vec<Play>> {
+    let start = range.start();
+    Ok(history::load()?
+        .into_iter()
+        .filter(|p| start.is_none_or(|s| started(p).is_some_and(|t| t >= s)))
+        .collect())
+}
+
+/// Groups plays by a key and ranks them by plays, then listening time
+pub fn rank<'p, K, F>(
+    plays: impl IntoIterator<Item = &'p Play>,
+    limit: usize,
+    key: F,
+) -> Vec<Ranked>
+where
+    K: std::hash::Hash + Eq,
+    F: Fn(&Play) -> Option<(K, String, Option<String>)>,
+{
+    let mut groups: HashMap<K, Ranked> = HashMap::new();
+    for play in plays {
+        let Some((key, name, artist)) = key(play) else {
+            continue;
+        };
+
+        let entry = groups.entry(key).or_insert_with(|| Ranked {
+            name,
+            artist,
+            plays: 0,
+            listened: 0.,
+        });
+        entry.plays += 1;
+        entry.listened += play.listened;
+    }
+
+    let mut ranked = groups.into_values().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.listened.total_cmp(&a.listened))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    ranked.truncate(limit);
+    ranked
+}
+
+fn counted(plays: &[Play]) -> impl Iterator<Item = &Play> { plays.iter().filter(|p| p.counts()) }
+
+/// Returns a play's primary artist with the current `artists_with_commas`
+pub fn primary_artist(play: &Play) -> String { Track::from(play).get_primary_artist() }
+
+/// Ranks artists by their primary artist
+pub fn top_artists(plays: &[Play], limit: usize) -> Vec<Ranked> {
+    rank(counted(plays), limit, |p| {
+        let artist = primary_artist(p);
+        Some((artist.clone(), artist, None))
+    })
+}
+
+/// Ranks albums, telling apart albums with the same name by their primary artist
+pub fn top_albums(plays: &[Play], limit: usize) -> Vec<Ranked> {
+    rank(counted(plays), limit, |p| {
+        if p.album.is_empty() || p.album == "<Unknown album>" {
+            return None;
+        }
+        let artist = primary_artist(p);
+        Some((
+            (p.album.clone(), artist.clone()),
+            p.album.clone(),
+            Some(artist),
+        ))
+    })
+}
+
+/// Ranks tracks by their path, falling back to their artist and title
+pub fn top_tracks(plays: &[Play], limit: usize) -> Vec<Ranked> {
+    rank(counted(plays), limit, |p| {
+        let key = p.path.as_ref().map_or_else(
+            || format!("{} - {}", p.artist, p.title),
+            |path| path.to_string_lossy().to_string(),
+        );
+        Some((key, p.title.clone(), Some(p.artist.clone())))
+    })
+}
+
+fn activity(plays: &[Play], bucket: Bucket) -> Vec<Activity> {
+    let mut counts = BTreeMap::new();
+    if bucket == Bucket::Hour {
+        counts.extend((0..24).map(|h| (format!("{h:02}"), 0)));
+    }
+
+    for play in plays.iter().filter(|p| p.counts()) {
+        let Some(time) = started(play) else {
+            continue;
+        };
+        let label = match bucket {
+            | Bucket::Day => time.format("%Y-%m-%d").to_string(),
+            | Bucket::Hour => format!("{:02}", time.hour()),
+        };
+        *counts.entry(label).or_insert(0) += 1;
+    }
+
+    counts
+        .into_iter()
+        .map(|(label, plays)| Activity { label, plays })
+        .collect()
+}
+
+/// Summarizes the plays within a range
+pub fn summarize(range: Range, bucket: Bucket, limit: usize) -> Result<Stats> {
+    let plays = plays_in(range)?;
+
+    Ok(Stats {
+        range,
+        plays: plays.iter().filter(|p| p.counts()).count(),
+        listened: plays.iter().map(|p| p.listened).sum(),
+        top_artists: top_artists(&plays, limit),
+        top_albums: top_albums(&plays, limit),
+        top_tracks: top_tracks(&plays, limit),
+        bucket,
+        activity: activity(&plays, bucket),
+    })
+}
+
+/// # Description
+/// Prints listening stats and returns an exit code.
+///
+/// Stats are read straight from the history file, so tuun doesn't need to be running.
+pub fn run(range: Range, bucket: Bucket, limit: usize, json: bool) -> i32 {
+    let stats = match summarize(range, bucket, limit) {
+        | Ok(s) => s,
+        | Err(e) => {
+            eprintln!("tuun: {e:#}");
+            return 1;
+        },
+    };
+
+    if json {
+        match serde_json::to_string(&stats) {
+            | Ok(s) => println!("{s}"),
+            | Err(e) => {
+                eprintln!("tuun: {e:#}");
+                return 1;
+            },
+        }
+    } else {
+        print_table(&stats);
+    }
+    0
+}
+
+fn print_table(stats: &Stats) {
+    println!("Listening stats for {}", stats.range.describe());
+    println!(
+        "{} plays, {} listened",
+        stats.plays,
+        format_duration(stats.listened)
+    );
+
+    for (title, ranked) in [
+        ("Top artists", &stats.top_artists),
+        ("Top albums", &stats.top_albums),
+        ("Top tracks", &stats.top_tracks),
+    ] {
+        println!("\n{title}");
+        if ranked.is_empty() {
+            println!("  (nothing yet)");
+            continue;
+        }
+
+        let width = ranked
+            .iter()
+            .map(|r| display_name(r).chars().count())
+            .max()
+            .unwrap_or(0);
+        for (i, r) in ranked.iter().enumerate() {
+            println!(
+                "{:>3}. {:<width$}  {:>4} plays  {}",
+                i + 1,
+                display_name(r),
+                r.plays,
+                format_duration(r.listened),
+            );
+        }
+    }
+
+    let title = match stats.bucket {
+        | Bucket::Day => "Plays per day",
+        | Bucket::Hour => "Plays per hour",
+    };
+    println!("\n{title}");
+
+    let most = stats.activity.iter().map(|a| a.plays).max().unwrap_or(0);
+    for a in &stats.activity {
+        // Scale bars to at most 40 characters
+        let bar = (a.plays * 40).checked_div(most).unwrap_or(0);
+        println!("  {:>10}  {:<40}  {}", a.label, "█".repeat(bar), a.plays);
+    }
+}
+
+fn display_name(ranked: &Ranked) -> String {
+    match &ranked.artist {
+        | Some(artist) => format!("{} - {artist}", ranked.name),
+        | None => ranked.name.clone(),
+    }
+}
+
+/// Formats seconds as hours and minutes, like `3h 07m`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn format_duration(secs: f64) -> String {
+    let mins = (secs.max(0.) / 60.).round() as u64;
+    if mins < 60 {
+        return format!("{mins}m");
+    }
+    format!("{}h {:02}m", mins / 60, mins % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Ending;
+
+    /// A play that counts, listened to for `listened` seconds
+    fn play(artist: &str, album: &str, title: &str, listened: f64) -> Play {
+        Play {
+            started: 1_750_000_000,
+            path: None,
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            primary_artist: String::new(),
+            album: album.to_owned(),
+            date: String::new(),
+            arturl: None,
+            duration: 200.,
+            listened,
+            ending: Ending::Natural,
+            scrobbled: false,
+        }
+    }
+
+    fn names(ranked: &[Ranked]) -> Vec<&str> { ranked.iter().map(|r| r.name.as_str()).collect() }
+
+    #[test]
+    fn artists_with_commas_stay_whole() {
+        let plays = [
+            play("Tyler, The Creator", "IGOR", "EARFQUAKE", 60.),
+            play("Tyler, The Creator, Frank Ocean", "Flower Boy", "911", 60.),
+            play("Tyler", "Other", "Other", 60.),
+            play("Someone, Someone Else", "Duets", "Duet", 60.),
+        ];
+
+        let top = top_artists(&plays, 10);
+        assert_eq!(names(&top), ["Tyler, The Creator", "Someone", "Tyler"]);
+        assert_eq!(top[0].plays, 2);
+    }
+
+    #[test]
+    fn ties_are_broken_by_listening_time_then_name() {
+        let plays = [
+            play("B", "", "", 60.),
+            play("A", "", "", 60.),
+            play("C", "", "", 90.),
+            play("D", "", "", 60.),
+            play("D", "", "", 60.),
+        ];
+
+        let top = top_artists(&plays, 10);
+        assert_eq!(names(&top), ["D", "C", "A", "B"]);
+        assert_eq!(names(&top_artists(&plays, 2)), ["D", "C"]);
+    }
+
+    #[test]
+    fn plays_that_dont_count_arent_ranked() {
+        let plays = [play("A", "", "", 60.), play("B", "", "", 10.)];
+        assert_eq!(names(&top_artists(&plays, 10)), ["A"]);
+    }
+
+    #[test]
+    fn albums_are_told_apart_by_artist() {
+        let plays = [
+            play("A", "Greatest Hits", "1", 60.),
+            play("A, B", "Greatest Hits", "2", 60.),
+            play("B", "Greatest Hits", "3", 60.),
+            play("B", "<Unknown album>", "4", 60.),
+            play("B", "", "5", 60.),
+        ];
+
+        let top = top_albums(&plays, 10);
+        let albums = top
+            .iter()
+            .map(|r| (r.name.as_str(), r.artist.as_deref(), r.plays))
+            .collect::<Vec<_>>();
+        assert_eq!(albums, [
+            ("Greatest Hits", Some("A"), 2),
+            ("Greatest Hits", Some("B"), 1),
+        ]);
+    }
+
+    #[test]
+    fn activity_counts_plays_per_bucket() {
+        // A minute apart then a day apart, in the middle of the day in any timezone
+        let mut plays = [
+            play("A", "", "", 60.),
+            play("A", "", "", 60.),
+            play("A", "", "", 60.),
+            play("A", "", "", 10.),
+        ];
+        plays[1].started += 60;
+        plays[2].started += 24 * 60 * 60;
+        let label = |play: &Play, format: &str| {
+            started(play)
+                .expect("Valid timestamp")
+                .format(format)
+                .to_string()
+        };
+
+        let days = activity(&plays, Bucket::Day)
+            .into_iter()
+            .map(|a| (a.label, a.plays))
+            .collect::<Vec<_>>();
+        assert_eq!(days, [
+            (label(&plays[0], "%Y-%m-%d"), 2),
+            (label(&plays[2], "%Y-%m-%d"), 1),
+        ]);
+
+        // Every hour is listed, even without plays
+        let hours = activity(&plays, Bucket::Hour);
+        assert_eq!(hours.len(), 24);
+        let busy = hours.iter().filter(|a| a.plays > 0).collect::<Vec<_>>();
+        assert_eq!(busy.len(), 1);
+        assert_eq!(busy[0].label, label(&plays[0], "%H"));
+        assert_eq!(busy[0].plays, 3);
+    }
+
+    #[test]
+    fn durations_are_hours_and_minutes() {
+        assert_eq!(format_duration(-5.), "0m");
+        assert_eq!(format_duration(29.), "0m");
+        assert_eq!(format_duration(59. * 60.), "59m");
+        assert_eq!(format_duration(3599.), "1h 00m");
+        assert_eq!(format_duration(187. * 60.), "3h 07m");
+    }
+}
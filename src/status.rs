@@ -0,0 +1,183 @@
+// src/status.rs
+//! Logic for printing the running instance's status
+
+use anyhow::{
+    Context,
+    Result,
+    bail,
+};
+use serde_json::Value;
+
+use crate::{
+    ctl::exit_code,
+    ipc::{
+        self,
+        Request,
+    },
+};
+
+/// # Description
+/// Prints the status of the running instance and returns an exit code.
+///
+/// The status is printed as JSON unless a format string is given.
+pub async fn run(format: Option<&str>) -> i32 { exit_code(status(format).await, ipc::SOCK_PATH) }
+
+async fn status(format: Option<&str>) -> Result<()> {
+    let status = ipc::request(&Request::Status).await?.unwrap_or_default();
+
+    let out = match format {
+        | Some(format) => render(format, &status)?,
+        | None => status.to_string(),
+    };
+
+    println!("{out}");
+    Ok(())
+}
+
+/// # Description
+/// Fills a format string's placeholders from the status.
+///
+/// Placeholders are the status's fields and the track's fields, like `{artist}` or `{paused}`.
+/// `{progress}` and `{duration}` are formatted as `m:ss`. Use `{{` and `}}` for literal braces.
+fn render(format: &str, status: &Value) -> Result<String> {
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            | '{' => {
+                let mut key = String::new();
+                loop {
+                    match chars.next() {
+                        | Some('{') if key.is_empty() => {
+                            out.push('{');
+                            break;
+                        },
+                        | Some('}') => {
+                            out.push_str(&lookup(&key, status)?);
+                            break;
+                        },
+                        | Some(c) => key.push(c),
+                        | None => bail!("Unclosed placeholder '{{{key}' in format string"),
+                    }
+                }
+            },
+            | '}' => {
+                if chars.next() != Some('}') {
+                    bail!("Unmatched '}}' in format string");
+                }
+                out.push('}');
+            },
+            | c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+fn lookup(key: &str, status: &Value) -> Result<String> {
+    let value = status
+        .get(key)
+        .or_else(|| status.get("track").and_then(|t| t.get(key)))
+        .with_context(|| format!("Unknown placeholder '{{{key}}}'"))?;
+
+    if matches!(key, "progress" | "duration")
+        && let Some(secs) = value.as_f64()
+    {
+        return Ok(format_time(secs));
+    }
+
+    Ok(match value {
+        | Value::String(s) => s.clone(),
+        | Value::Null => String::new(),
+        | v => v.to_string(),
+    })
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn format_time(secs: f64) -> String {
+    let secs = secs.max(0.).trunc() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn status() -> Value {
+        json!({
+            "paused": false,
+            "volume": 85,
+            "primary_artist": "Tyler, The Creator",
+            "track": {
+                "title": "EARFQUAKE",
+                "artist": "Tyler, The Creator",
+                "album": "IGOR",
+                "album_artist": null,
+                "progress": 75.9,
+                "duration": 190.2,
+            },
+        })
+    }
+
+    #[test]
+    fn placeholders_are_filled() {
+        let status = status();
+        for (format, expected) in [
+            ("", ""),
+            ("no placeholders", "no placeholders"),
+            ("{title}", "EARFQUAKE"),
+            ("{artist} - {title}", "Tyler, The Creator - EARFQUAKE"),
+            ("{primary_artist}", "Tyler, The Creator"),
+            ("{paused} {volume}%", "false 85%"),
+            ("[{album_artist}]", "[]"),
+            ("{progress}/{duration}", "1:15/3:10"),
+        ] {
+            assert_eq!(
+                render(format, &status).expect("Format should render"),
+                expected,
+                "format: {format}"
+            );
+        }
+    }
+
+    #[test]
+    fn braces_are_escaped_by_doubling() {
+        let status = status();
+        for (format, expected) in [
+            ("{{", "{"),
+            ("}}", "}"),
+            ("{{title}}", "{title}"),
+            ("{{{title}}}", "{EARFQUAKE}"),
+        ] {
+            assert_eq!(
+                render(format, &status).expect("Format should render"),
+                expected,
+                "format: {format}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_formats_are_errors() {
+        let status = status();
+        for (format, error) in [
+            ("{nope}", "Unknown placeholder '{nope}'"),
+            ("{}", "Unknown placeholder '{}'"),
+            ("{title", "Unclosed placeholder '{title' in format string"),
+            ("title}", "Unmatched '}' in format string"),
+            ("{title}}", "Unmatched '}' in format string"),
+        ] {
+            let e = render(format, &status).expect_err("Format should be rejected");
+            assert_eq!(e.to_string(), error, "format: {format}");
+        }
+    }
+
+    #[test]
+    fn top_level_fields_come_first() {
+        let status = json!({ "title": "top", "track": { "title": "track" } });
+        assert_eq!(lookup("title", &status).expect("Known key"), "top");
+    }
+}
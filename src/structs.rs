@@ -6,7 +6,11 @@ use std::{
     },
     path::PathBuf,
     sync::atomic::Ordering,
-    time::Duration,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
 };
 
 use anyhow::{
@@ -14,11 +18,7 @@ use anyhow::{
     Result,
     bail,
 };
-use id3::{
-    Content,
-    Tag,
-    TagLike,
-};
+use serde::Serialize;
 use serde_json::Value;
 use tracing::{
     debug,
@@ -33,62 +33,60 @@ use crate::{
     CONFIG,
     config::ColorConfig,
     integrations,
+    loved,
     mpv::{
         LOOPED,
         MUTED,
         VOLUME,
         send_command,
     },
+    tags::Tags,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[allow(clippy::struct_field_names)]
 pub struct Track {
-    pub arturl:   String,
-    pub srcurl:   Option<String>,
-    pub title:    String,
-    pub artist:   String,
-    pub album:    String,
-    pub date:     String,
-    pub progress: f64,
-    pub duration: f64,
+    pub path:         Option<PathBuf>,
+    pub arturl:       String,
+    pub srcurl:       Option<String>,
+    pub title:        String,
+    pub artist:       String,
+    pub album:        String,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub date:         String,
+    pub progress:     f64,
+    pub duration:     f64,
 }
 
 impl Default for Track {
     fn default() -> Self {
         Self {
-            arturl:   String::new(),
-            srcurl:   None,
-            title:    String::new(),
-            artist:   String::new(),
-            album:    String::new(),
-            date:     String::new(),
-            progress: 0.0,
-            duration: 1000.,
+            path:         None,
+            arturl:       String::new(),
+            srcurl:       None,
+            title:        String::new(),
+            artist:       String::new(),
+            album:        String::new(),
+            album_artist: None,
+            track_number: None,
+            date:         String::new(),
+            progress:     0.0,
+            duration:     1000.,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct LastFM<'lfm> {
-    pub apikey:   &'lfm str,
-    pub secret:   &'lfm str,
-    pub username: &'lfm str,
-    pub password: &'lfm str,
-}
+pub fn strip_null(s: &str) -> String { s.replace('\0', "") }
 
-impl LastFM<'_> {
-    pub fn new() -> Self {
-        Self {
-            apikey:   &CONFIG.lastfm.apikey,
-            secret:   &CONFIG.lastfm.secret,
-            username: &CONFIG.lastfm.user,
-            password: &CONFIG.lastfm.password,
-        }
-    }
+/// Returns the current unix timestamp
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
-pub fn strip_null(s: &str) -> String { s.replace('\0', "") }
-
 pub fn urlencode(url: &str) -> String {
     let (proto, rest_of_url) = url
         .find("://")
@@ -147,66 +145,52 @@ impl Track {
         Ok(PathBuf::from(filename))
     }
 
-    #[instrument(skip(data, tag))]
-    pub fn get_arturl(data: &serde_json::Map<String, Value>, tag: Option<&Tag>) -> Option<String> {
+    #[instrument(skip(data, tags))]
+    pub fn get_arturl(
+        data: &serde_json::Map<String, Value>,
+        tags: Option<&Tags>,
+    ) -> Option<String> {
         if let Some(url) = data.get("arturl").and_then(|v| v.as_str()) {
             debug!("Using key 'arturl' from mpv's metadata");
             return Some(url.to_string());
         }
 
-        if let Some(tag) = tag {
-            let arturl = tag.frames().find_map(|f| match f.content() {
-                | Content::ExtendedLink(l) if l.description == "Cover" => Some(l.link.clone()),
-                | Content::ExtendedText(t) if t.description == "arturl" => {
-                    Some(strip_null(&t.value))
-                },
-                | _ => None,
-            });
-
-            if arturl.is_none() {
-                warn!("Couldn't find srcurl in extended text or cover in extended link frames");
+        if let Some(tags) = tags {
+            if tags.arturl.is_none() {
+                warn!("Couldn't find arturl or cover in the track's tags");
             }
 
-            return arturl;
+            return tags.arturl.clone();
         }
 
         None
     }
 
-    #[instrument(skip(data, tag))]
-    pub fn get_srcurl(data: &serde_json::Map<String, Value>, tag: Option<&Tag>) -> Option<String> {
+    #[instrument(skip(data, tags))]
+    pub fn get_srcurl(
+        data: &serde_json::Map<String, Value>,
+        tags: Option<&Tags>,
+    ) -> Option<String> {
         if let Some(url) = data.get("srcurl").and_then(|v| v.as_str()) {
             debug!("Using key 'srcurl' from mpv's metadata");
             return Some(url.to_string());
         }
 
-        if let Some(tag) = tag {
-            let srcurl = tag.frames().find_map(|f| match f.content() {
-                | Content::ExtendedLink(l) if l.description == "Source" => Some(l.link.clone()),
-                | Content::ExtendedText(t) if t.description == "srcurl" => {
-                    Some(strip_null(&t.value))
-                },
-                | _ => None,
-            });
-
-            if srcurl.is_none() {
-                warn!("Couldn't find srcurl in extended text or cover in extended link frames");
+        if let Some(tags) = tags {
+            if tags.srcurl.is_none() {
+                warn!("Couldn't find srcurl or source in the track's tags");
             }
 
-            return srcurl;
+            return tags.srcurl.clone();
         }
 
         None
     }
 
-    #[instrument(skip(data, tag))]
-    pub fn get_artists(data: &serde_json::Map<String, Value>, tag: Option<&Tag>) -> String {
-        if let Some(tag) = tag {
-            let artist = tag.artist();
-            match artist {
-                | Some(a) => return a.split('\0').collect::<Vec<_>>().join(", "),
-                | None => return String::from("<Unknown artist>"),
-            }
+    #[instrument(skip(data, tags))]
+    pub fn get_artists(data: &serde_json::Map<String, Value>, tags: Option<&Tags>) -> String {
+        if let Some(artist) = tags.and_then(Tags::artist) {
+            return artist;
         }
 
         data.get("artist")
@@ -239,42 +223,50 @@ impl Track {
             },
         };
 
-        let tag = if let Some(f) = filepath
-            && let Some(ext) = f.extension()
-            && ext.eq_ignore_ascii_case("mp3")
-        {
-            match Tag::read_from_path(&f) {
-                | Ok(t) => Some(t),
-                | Err(e) => {
-                    error!("Couldn't read tag from path '{}': {e}", f.display());
-                    None
-                },
-            }
-        } else {
-            None
-        };
+        let tags = filepath.as_ref().and_then(|f| match Tags::read(f) {
+            | Ok(t) => Some(t),
+            | Err(e) => {
+                error!("Couldn't read tags from path '{}': {e}", f.display());
+                None
+            },
+        });
 
+        self.path = filepath;
         self.title = data
             .get("title")
             .and_then(|v| v.as_str())
             .unwrap_or("<Unknown title>")
             .to_string();
-        self.artist = Self::get_artists(&data, tag.as_ref());
+        self.artist = Self::get_artists(&data, tags.as_ref());
         self.album = data
             .get("album")
             .and_then(|v| v.as_str())
             .unwrap_or("<Unknown album>")
             .to_string();
+        self.album_artist = tags
+            .as_ref()
+            .and_then(|t| t.album_artist.clone())
+            .or_else(|| {
+                data.get("album_artist")
+                    .and_then(Value::as_str)
+                    .map(ToOwned::to_owned)
+            });
+        // mpv reports track numbers like "3/12"
+        self.track_number = tags.as_ref().and_then(|t| t.track_number).or_else(|| {
+            data.get("track")
+                .and_then(Value::as_str)
+                .and_then(|t| t.split('/').next()?.trim().parse().ok())
+        });
         self.date = data
             .get("date")
             .and_then(|v| v.as_str())
             .unwrap_or("<Unknown date>")
             .to_string();
 
-        self.arturl = Self::get_arturl(&data, tag.as_ref())
+        self.arturl = Self::get_arturl(&data, tags.as_ref())
             .map_or_else(|| CONFIG.discord.fallback_art.clone(), |u| urlencode(&u));
 
-        self.srcurl = Self::get_srcurl(&data, tag.as_ref()).map(|u| urlencode(&u));
+        self.srcurl = Self::get_srcurl(&data, tags.as_ref()).map(|u| urlencode(&u));
 
         debug!("Attempting to find duration");
         // duration is not technically metadata but i count it as such
@@ -309,6 +301,7 @@ impl Track {
     fn format_metadata(&self) -> String {
         let loop_display = if LOOPED.load(Ordering::Relaxed) { " (looped)" } else { "" };
         let mute_display = if MUTED.load(Ordering::Relaxed) { " (muted)" } else { "" };
+        let love_display = if loved::is_loved(self) { " ♥" } else { "" };
 
         let theme = Theme::from(&CONFIG.color);
 
@@ -316,7 +309,7 @@ impl Track {
             "\
 {b}{p}TUUN {ver}{r}
 
-{b}{p}01 {s}{sep} Ttl - {t}{title}{r}
+{b}{p}01 {s}{sep} Ttl - {t}{title}{loved}{r}
 {b}{p}02 {s}{sep} Art - {t}{artist}{r}
 {b}{p}03 {s}{sep} Alb - {t}{album}{r}
 {b}{p}04 {s}{sep} Dte - {t}{date}{r}
@@ -330,6 +323,7 @@ impl Track {
             sep = ":::",
             ver = env!("CARGO_PKG_VERSION"),
             title = self.title,
+            loved = love_display,
             artist = self.artist,
             album = self.album,
             date = self.date,
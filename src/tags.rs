@@ -0,0 +1,214 @@
+// src/tags.rs
+//! Logic for reading tags regardless of format
+//!
+//! mp3s are read with `id3` so extended link and text frames keep working. Everything else
+//! (Vorbis comments, MP4 atoms, APE tags, etc.) is read with `lofty`.
+//!
+//! Art and source urls are looked up under the `arturl`/`cover` and `srcurl`/`source` keys,
+//! ignoring case. MP4 freeform atoms like `----:com.apple.iTunes:arturl` are matched by name.
+
+use std::{
+    borrow::Cow,
+    path::Path,
+};
+
+use anyhow::Result;
+use id3::{
+    Content,
+    Tag as Id3Tag,
+    TagLike,
+    frame::PictureType as Id3PictureType,
+};
+use lofty::{
+    file::{
+        AudioFile,
+        TaggedFileExt,
+    },
+    picture::{
+        MimeType,
+        PictureType,
+    },
+    tag::{
+        Accessor,
+        ItemKey,
+        Tag,
+    },
+};
+use tracing::{
+    debug,
+    instrument,
+};
+
+use crate::structs::strip_null;
+
+const ARTURL_KEYS: [&str; 2] = ["arturl", "cover"];
+const SRCURL_KEYS: [&str; 2] = ["srcurl", "source"];
+
+/// The tags tuun cares about
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    pub title:        Option<String>,
+    /// Every artist, including those from multi-value fields
+    pub artists:      Vec<String>,
+    pub album:        Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub date:         Option<String>,
+    pub arturl:       Option<String>,
+    pub srcurl:       Option<String>,
+    /// Whether the file has embedded pictures
+    pub has_picture:  bool,
+    /// Duration in seconds, if known
+    pub duration:     Option<f64>,
+}
+
+impl Tags {
+    /// Reads a file's tags
+    #[instrument]
+    pub fn read(path: &Path) -> Result<Self> {
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+        {
+            Self::read_id3(path)
+        } else {
+            Self::read_lofty(path)
+        }
+    }
+
+    /// Returns the artists joined by commas
+    pub fn artist(&self) -> Option<String> {
+        (!self.artists.is_empty()).then(|| self.artists.join(", "))
+    }
+
+    fn read_id3(path: &Path) -> Result<Self> {
+        let tag = Id3Tag::read_from_path(path)?;
+
+        let link = |description: &str, key: &str| {
+            tag.frames().find_map(|f| match f.content() {
+                | Content::ExtendedLink(l) if l.description == description => Some(l.link.clone()),
+                | Content::ExtendedText(t) if t.description == key => Some(strip_null(&t.value)),
+                | _ => None,
+            })
+        };
+
+        // mp3s rarely carry TLEN, so fall back to measuring the audio
+        let duration = tag
+            .duration()
+            .map(|ms| f64::from(ms) / 1000.)
+            .or_else(|| read_duration(path));
+
+        Ok(Self {
+            title: tag.title().map(ToOwned::to_owned),
+            artists: tag
+                .artist()
+                .map(|a| a.split('\0').map(ToOwned::to_owned).collect())
+                .unwrap_or_default(),
+            album: tag.album().map(ToOwned::to_owned),
+            album_artist: tag.album_artist().map(ToOwned::to_owned),
+            track_number: tag.track(),
+            date: tag
+                .date_recorded()
+                .map(|d| d.to_string())
+                .or_else(|| tag.year().map(|y| y.to_string())),
+            arturl: link("Cover", "arturl"),
+            srcurl: link("Source", "srcurl"),
+            has_picture: tag.pictures().next().is_some(),
+            duration,
+        })
+    }
+
+    fn read_lofty(path: &Path) -> Result<Self> {
+        let file = lofty::read_from_path(path)?;
+        let duration = Some(file.properties().duration().as_secs_f64()).filter(|d| *d > 0.);
+
+        let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+            debug!("No tags in '{}'", path.display());
+            return Ok(Self { duration, ..Self::default() });
+        };
+
+        Ok(Self {
+            title: tag.title().map(Cow::into_owned),
+            artists: tag
+                .get_strings(&ItemKey::TrackArtist)
+                .flat_map(|a| a.split('\0'))
+                .map(ToOwned::to_owned)
+                .collect(),
+            album: tag.album().map(Cow::into_owned),
+            album_artist: tag.get_string(&ItemKey::AlbumArtist).map(ToOwned::to_owned),
+            track_number: tag.track(),
+            date: tag
+                .get_string(&ItemKey::RecordingDate)
+                .map(ToOwned::to_owned)
+                .or_else(|| tag.year().map(|y| y.to_string())),
+            arturl: find_custom(tag, &ARTURL_KEYS),
+            srcurl: find_custom(tag, &SRCURL_KEYS),
+            has_picture: !tag.pictures().is_empty(),
+            duration,
+        })
+    }
+}
+
+/// An embedded picture
+#[derive(Debug, Clone)]
+pub struct Picture {
+    pub mime_type: String,
+    pub data:      Vec<u8>,
+}
+
+/// # Description
+/// Reads a file's first embedded picture.
+///
+/// Front covers are preferred when there are several.
+pub fn read_picture(path: &Path) -> Option<Picture> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+    {
+        let tag = Id3Tag::read_from_path(path).ok()?;
+        let picture = tag
+            .pictures()
+            .find(|p| p.picture_type == Id3PictureType::CoverFront)
+            .or_else(|| tag.pictures().next())?;
+        return Some(Picture {
+            mime_type: picture.mime_type.clone(),
+            data:      picture.data.clone(),
+        });
+    }
+
+    let file = lofty::read_from_path(path).ok()?;
+    let tag = file.primary_tag().or_else(|| file.first_tag())?;
+    let picture = tag
+        .pictures()
+        .iter()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| tag.pictures().first())?;
+    Some(Picture {
+        mime_type: picture
+            .mime_type()
+            .map_or("image/jpeg", MimeType::as_str)
+            .to_owned(),
+        data:      picture.data().to_vec(),
+    })
+}
+
+/// Finds the first text item whose key is one of `keys`, ignoring case and freeform prefixes
+fn find_custom(tag: &Tag, keys: &[&str]) -> Option<String> {
+    tag.items().find_map(|item| {
+        let ItemKey::Unknown(key) = item.key() else {
+            return None;
+        };
+        let name = key.rsplit(':').next().unwrap_or(key);
+        if !keys.iter().any(|k| name.eq_ignore_ascii_case(k)) {
+            return None;
+        }
+        item.value().text().map(strip_null)
+    })
+}
+
+fn read_duration(path: &Path) -> Option<f64> {
+    lofty::read_from_path(path)
+        .ok()
+        .map(|f| f.properties().duration().as_secs_f64())
+        .filter(|d| *d > 0.)
+}
@@ -0,0 +1,291 @@
+// src/watcher.rs
+//! Logic for watching the library for changes
+//!
+//! Every directory under the library roots is watched with inotify. Once things settle after a
+//! change, the library is rescanned, the index and generated playlists are updated, and new
+//! tracks are added to mpv's playlist if it's playing `all.tpl`.
+//!
+//! With `auto_queue`, new tracks are queued to play next instead, once they've gone unmodified for
+//! `auto_queue_min_age` seconds.
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use inotify::{
+    EventMask,
+    Inotify,
+    WatchMask,
+    Watches,
+};
+use serde_json::json;
+use tokio::time::{
+    Instant,
+    MissedTickBehavior,
+    interval,
+    sleep,
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    instrument,
+    trace,
+    warn,
+};
+
+use crate::{
+    CONFIG,
+    index::Index,
+    library::{
+        self,
+        IGNORE_FILE,
+        ScanOptions,
+    },
+    mpv,
+    playlists::{
+        self,
+        ALL_PLAYLIST,
+    },
+    queue::{
+        self,
+        Mode,
+    },
+};
+
+/// How long the library has to be quiet before it's rescanned
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How often to check whether new tracks are old enough to be auto-queued
+const PENDING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// # Description
+/// Watches the library roots and keeps the index and generated playlists current.
+///
+/// Takes ownership of the index built at startup.
+#[instrument(skip(index))]
+pub async fn watch(mut index: Index) -> Result<()> {
+    let inotify = Inotify::init()?;
+    let mut watches = inotify.watches();
+    add_watches(&mut watches);
+
+    let mut events = inotify.into_event_stream([0; 4096])?;
+    info!("Watching the library for changes");
+
+    // New tracks waiting to be auto-queued
+    let mut pending = Vec::new();
+    let mut pending_check = interval(PENDING_INTERVAL);
+    pending_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    // Downloads and file managers tend to make a burst of changes, so wait for a lull
+    let mut changed = false;
+    let settle = sleep(SETTLE_TIME);
+    tokio::pin!(settle);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+                match event {
+                    | Ok(event) if changed || is_relevant(event.mask, event.name.as_deref()) => {
+                        trace!("Library changed: {:?}", event.mask);
+                        changed = true;
+                        settle.as_mut().reset(Instant::now() + SETTLE_TIME);
+                    },
+                    | Ok(_) => {},
+                    | Err(e) => {
+                        // Events may have been lost, so rescan to be safe
+                        warn!("Failed to read library events: {e}");
+                        changed = true;
+                        settle.as_mut().reset(Instant::now() + SETTLE_TIME);
+                    },
+                }
+            },
+            () = &mut settle, if changed => {
+                changed = false;
+
+                debug!("Library changed, rescanning");
+                let added;
+                (index, added) = refresh(index).await?;
+                add_watches(&mut watches);
+
+                if CONFIG.general.auto_queue {
+                    pending.extend(added);
+                    pending.sort();
+                    pending.dedup();
+                } else if mpv::starting_playlist() == ALL_PLAYLIST {
+                    append(&added).await;
+                }
+            },
+            _ = pending_check.tick(), if !pending.is_empty() => {
+                pending = auto_queue(pending).await;
+            },
+        }
+    }
+}
+
+/// Whether an event could change which tracks are in the library
+fn is_relevant(mask: EventMask, name: Option<&OsStr>) -> bool {
+    if mask.contains(EventMask::ISDIR) {
+        return true;
+    }
+
+    name.is_none_or(|n| n == IGNORE_FILE || library::is_audio(Path::new(n)))
+}
+
+/// Watches every directory under the library roots, including newly created ones
+fn add_watches(watches: &mut Watches) {
+    let mask = WatchMask::CREATE
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM
+        | WatchMask::DELETE;
+
+    let mut visited = HashSet::new();
+    for root in library::roots().iter().filter(|r| r.path.is_dir()) {
+        add_watches_under(watches, mask, &root.path, root.options, &mut visited);
+    }
+    trace!("Watching {} directories", visited.len());
+}
+
+fn add_watches_under(
+    watches: &mut Watches,
+    mask: WatchMask,
+    dir: &Path,
+    options: ScanOptions,
+    visited: &mut HashSet<PathBuf>,
+) {
+    if let Ok(canonical) = dir.canonicalize()
+        && !visited.insert(canonical)
+    {
+        return;
+    }
+
+    if let Err(e) = watches.add(dir, mask) {
+        warn!("Failed to watch '{}': {e}", dir.display());
+        return;
+    }
+
+    if !options.recursive {
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.map_while(Result::ok) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+
+        if file_type.is_dir()
+            || (file_type.is_symlink() && options.follow_symlinks && path.is_dir())
+        {
+            add_watches_under(watches, mask, &path, options, visited);
+        }
+    }
+}
+
+/// # Description
+/// Rescans the library, then updates the index and generated playlists.
+///
+/// Returns the updated index and the tracks that are new to it.
+async fn refresh(mut index: Index) -> Result<(Index, Vec<PathBuf>)> {
+    let before = index
+        .entries()
+        .map(|e| e.path.clone())
+        .collect::<HashSet<_>>();
+
+    // Walking the library blocks, so keep it off the runtime's threads
+    let index = tokio::task::spawn_blocking(move || {
+        let parsed = index.refresh(&library::scan_roots(|_| true));
+        debug!("Reparsed {parsed} tracks");
+
+        if let Err(e) = index.save() {
+            warn!("Failed to save the library index: {e:#}");
+        }
+        if let Err(e) = playlists::write_all_playlist(&index) {
+            warn!("{e:#}");
+        }
+        if let Err(e) = playlists::write_recent_playlist(&index) {
+            warn!("{e:#}");
+        }
+        index
+    })
+    .await?;
+
+    let added = index
+        .entries()
+        .filter(|e| !before.contains(&e.path))
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+    info!(
+        "Library now has {} tracks ({} new)",
+        index.len(),
+        added.len()
+    );
+
+    Ok((index, added))
+}
+
+/// Adds new tracks to the end of mpv's playlist
+async fn append(added: &[PathBuf]) {
+    for track in added.iter().filter(|t| library::in_all(t)) {
+        match mpv::command(json!(["loadfile", track, "append"])).await {
+            | Ok(_) => info!("Added new track '{}' to the playlist", track.display()),
+            | Err(e) => error!("Failed to add new track '{}': {e:#}", track.display()),
+        }
+    }
+}
+
+/// # Description
+/// Queues the pending tracks that have gone unmodified for long enough to play next.
+///
+/// Returns the tracks that are still pending. Tracks that vanished are dropped.
+async fn auto_queue(pending: Vec<PathBuf>) -> Vec<PathBuf> {
+    let min_age = Duration::from_secs(CONFIG.general.auto_queue_min_age);
+    let now = SystemTime::now();
+
+    let mut ready = Vec::new();
+    let mut waiting = Vec::new();
+    for track in pending {
+        let Ok(modified) = fs::metadata(&track).and_then(|m| m.modified()) else {
+            debug!(
+                "New track '{}' vanished before it was queued",
+                track.display()
+            );
+            continue;
+        };
+
+        if now.duration_since(modified).unwrap_or_default() >= min_age {
+            ready.push(track.to_string_lossy().to_string());
+        } else {
+            waiting.push(track);
+        }
+    }
+
+    if !ready.is_empty() {
+        match queue::add(&ready, Mode::Next).await {
+            | Ok(()) => info!("Auto-queued {} new tracks", ready.len()),
+            | Err(e) => error!("Failed to auto-queue new tracks: {e:#}"),
+        }
+    }
+
+    waiting
+}